@@ -1,39 +1,72 @@
-use std::{collections::HashSet, time::Instant};
+use std::collections::HashMap;
 
 use bevy::{
     app::AppExit,
-    prelude::{EventReader, EventWriter, Query, Res, ResMut, Without},
+    prelude::{EventReader, EventWriter, Query, Res, With, Without},
 };
 
-use crate::{EndGameEvent, combat::Dead, creature::CreatureType};
+use crate::{
+    combat::Dead,
+    creature::CreatureType,
+    position::Position,
+    scenario::{Scenario, VictoryCondition},
+    EndGameEvent, TickCount,
+};
+
+/// Creatures still in the battle. Those waiting for somewhere to start aren't in it yet.
+pub type Survivor = (With<CreatureType>, With<Position>, Without<Dead>);
 
 pub fn creature_type_count(
-    query: Query<&CreatureType, Without<Dead>>,
-    mut log: ResMut<Vec<String>>,
+    query: Query<&CreatureType, Survivor>,
+    scenario: Res<Scenario>,
+    tick_count: Res<TickCount>,
     mut end_game_event: EventWriter<EndGameEvent>,
-    game_start_time: Res<Instant>,
 ) {
-    let creature_set: HashSet<&CreatureType> =
-        query.iter().fold(HashSet::new(), |mut acc, creature_type| {
-            acc.insert(&creature_type);
+    let creature_counts: HashMap<&CreatureType, usize> =
+        query.iter().fold(HashMap::new(), |mut acc, creature_type| {
+            *acc.entry(creature_type).or_insert(0) += 1;
             acc
         });
 
-    let num_creatures_remaining = creature_set.len();
-    match num_creatures_remaining {
-        0 => {
-            log.push(format!("Game over!  Everybody is dead!  Everybody loses!",));
-            end_game_event.send(EndGameEvent);
-        }
-        1 => {
-            log.push(format!(
-                "Game over!  Winner: {:?}s after {} seconds",
-                creature_set.iter().next().unwrap(),
-                game_start_time.elapsed().as_secs()
-            ));
-            end_game_event.send(EndGameEvent);
+    for condition in scenario.victory.iter() {
+        match condition {
+            VictoryCondition::LastFactionStanding => match creature_counts.len() {
+                0 => {
+                    end_game_event.send(EndGameEvent {
+                        winner: None,
+                        out_of_time: false,
+                    });
+                    return;
+                }
+                1 => {
+                    end_game_event.send(EndGameEvent {
+                        winner: Some((*creature_counts.keys().next().unwrap()).clone()),
+                        out_of_time: false,
+                    });
+                    return;
+                }
+                _ => (),
+            },
+            VictoryCondition::TickLimit(ticks) if tick_count.0 >= *ticks => {
+                let most_survivors = creature_counts.values().max().copied().unwrap_or(0);
+                let mut leaders = creature_counts
+                    .iter()
+                    .filter(|(_, count)| **count == most_survivors);
+
+                // A tie for the most survivors is a draw
+                let winner = match (leaders.next(), leaders.next()) {
+                    (Some((creature_type, _)), None) => Some((*creature_type).clone()),
+                    _ => None,
+                };
+
+                end_game_event.send(EndGameEvent {
+                    winner,
+                    out_of_time: true,
+                });
+                return;
+            }
+            VictoryCondition::TickLimit(_) => (),
         }
-        _ => (),
     }
 }
 
@@ -42,3 +75,12 @@ pub fn end_game(mut exit: EventWriter<AppExit>, mut end_game_event: EventReader<
         exit.send(AppExit)
     }
 }
+
+// Headless games have no log on screen, so the outcome is printed once the game is over
+pub fn print_result(log: Res<Vec<String>>, mut end_game_event: EventReader<EndGameEvent>) {
+    for _ in end_game_event.iter() {
+        if let Some(result) = log.last() {
+            println!("{}", result);
+        }
+    }
+}
@@ -1,20 +1,20 @@
 use std::{convert::TryInto, io::stdout};
 
-use bevy::prelude::{Commands, Entity, Query, Res, ResMut, With, Without};
+use bevy::prelude::{Commands, Entity, EventWriter, Mut, Query, Res, ResMut, With, Without};
 use crossterm::{
     cursor,
     style::{self, Color},
     QueueableCommand,
 };
 use rand::Rng;
+use rand_chacha::ChaCha12Rng;
 
 use crate::{
     components::{Name, Severity, SeverityLevel},
-    creature::CreatureType,
-    equipment::{
-        get_armour, get_shield, get_weapon, EquippedArmour, EquippedShield, EquippedWeapon,
-    },
-    map::Map,
+    creature::{CombatStats, CreatureType},
+    equipment::{EquippedArmour, EquippedShield, EquippedWeapon, ItemCatalogue, WeaponStats},
+    initiative::TurnOrder,
+    map::{Dungeon, Level},
     path::Moves,
     position::{distance2d_pythagoras_squared, Position},
     render::Render,
@@ -48,171 +48,260 @@ impl Death for Hp {
 
 pub struct Dead;
 
+// Query filter for creatures still in the fight
+pub type Living = (With<Hp>, Without<Dead>);
+
+pub struct ArmourClass {
+    pub base: i32,
+    pub armour: i32,
+    pub shield: i32,
+}
+
+impl ArmourClass {
+    pub fn total(&self) -> i32 {
+        self.base + self.armour + self.shield
+    }
+}
+
+// A 1d20 attack roll plus the attacker's bonus against the target's armour class
+pub struct AttackRoll {
+    pub roll: i32,
+    pub attack_bonus: i32,
+    pub armour_class: ArmourClass,
+}
+
+impl AttackRoll {
+    pub fn is_hit(&self) -> bool {
+        self.roll + self.attack_bonus >= self.armour_class.total()
+    }
+}
+
+pub enum AttackOutcome {
+    // Creatures without any aggression greet their target instead of attacking
+    Greeting,
+    Miss(AttackRoll),
+    Hit {
+        roll: AttackRoll,
+        weapon: WeaponStats,
+        damage: i32,
+        // True when this hit took the target from alive to dead
+        killed: bool,
+    },
+}
+
+// Sent every time a creature attempts an attack on an adjacent creature
+pub struct AttackEvent {
+    pub attacker: Entity,
+    pub attacker_name: String,
+    pub target: Entity,
+    pub target_name: String,
+    pub outcome: AttackOutcome,
+}
+
+pub struct DeathEvent {
+    pub entity: Entity,
+    pub name: String,
+}
+
+// Everything about a creature that goes into the attacks it makes
+type Attacker<'a> = (
+    Entity,
+    &'a Name,
+    &'a Position,
+    &'a Level,
+    &'a Aggression,
+    &'a CreatureType,
+    &'a CombatStats,
+    Option<&'a EquippedWeapon>,
+);
+
+// Everything about a creature that goes into defending against an attack
+type Target<'a> = (
+    Entity,
+    &'a mut Hp,
+    &'a Name,
+    &'a Position,
+    &'a Level,
+    &'a CreatureType,
+    &'a CombatStats,
+    Option<&'a EquippedArmour>,
+    Option<&'a EquippedShield>,
+);
+
 pub fn fight(
-    subject_query: Query<(
-        &Name,
-        &Position,
-        &Aggression,
-        &CreatureType,
-        Option<&EquippedWeapon>,
-    )>,
-    mut target_query: Query<
-        (
-            &mut Hp,
-            &Name,
-            &Position,
-            &CreatureType,
-            Option<&EquippedArmour>,
-            Option<&EquippedShield>,
-        ),
-        Without<Dead>,
-    >,
-    mut log: ResMut<Vec<String>>,
+    subject_query: Query<Attacker>,
+    mut target_query: Query<Target, Without<Dead>>,
+    turn_order: Res<TurnOrder>,
+    items: Res<ItemCatalogue>,
+    mut attack_event: EventWriter<AttackEvent>,
+    mut rng: ResMut<ChaCha12Rng>,
 ) {
-    let mut rng = rand::thread_rng();
-
-    for (
-        subject_name,
-        subject_position,
-        subject_aggression,
-        subject_creature_type,
-        subject_equipped_weapon,
-    ) in subject_query.iter()
-    {
-        for (
-            mut target_hp,
-            target_name,
-            target_position,
-            target_creature_type,
-            target_equipped_armour,
-            target_equipped_shield,
-        ) in target_query.iter_mut()
-        {
-            // Same typed creatures do not attack one another
-            if subject_creature_type == target_creature_type {
+    // Creatures next to more than one target attack them in the order they were spawned in
+    let mut targets: Vec<Entity> = target_query.iter_mut().map(|(entity, ..)| entity).collect();
+    targets.sort();
+
+    for &subject_entity in turn_order.0.iter() {
+        let (
+            _,
+            subject_name,
+            subject_position,
+            subject_level,
+            subject_aggression,
+            subject_creature_type,
+            subject_stats,
+            subject_equipped_weapon,
+        ) = match subject_query.get(subject_entity) {
+            Ok(subject) => subject,
+            Err(_) => continue,
+        };
+
+        // Creatures killed earlier in the turn order don't get to strike back
+        if let Ok((_, subject_hp, ..)) = target_query.get_mut(subject_entity) {
+            if subject_hp.is_dead() {
                 continue;
             }
+        }
 
-            if distance2d_pythagoras_squared(subject_position, target_position) <= 2.0 {
-                match subject_aggression.get_severity() {
+        for &target_entity in targets.iter() {
+            let (
+                _,
+                mut target_hp,
+                target_name,
+                target_position,
+                target_level,
+                target_creature_type,
+                target_stats,
+                target_equipped_armour,
+                target_equipped_shield,
+            ) = target_query.get_mut(target_entity).unwrap();
+
+            // Same typed creatures do not attack one another, and nobody hits a corpse
+            if subject_creature_type == target_creature_type || target_hp.is_dead() {
+                continue;
+            }
+
+            if subject_level == target_level
+                && distance2d_pythagoras_squared(subject_position, target_position) <= 2.0
+            {
+                let outcome = match subject_aggression.get_severity() {
                     SeverityLevel::Moderate | SeverityLevel::Max => {
-                        let roll = rng.gen_range(1..=20);
-                        let shield = get_shield(target_equipped_shield);
-                        let armour = get_armour(target_equipped_armour);
-
-                        let total_ac = target_creature_type.get_stats().armour_class
-                            + shield.get_stats().armour_class
-                            + armour.get_stats().armour_class;
-
-                        match roll + subject_creature_type.get_stats().attack_bonus {
-                            roll if roll >= total_ac => {
-                                let weapon = get_weapon(subject_equipped_weapon);
-                                let damage = weapon.get_damage();
-                                let weapon_stats = weapon.get_stats();
-
-                                target_hp.0 = target_hp.0 - damage;
-
-                                log.push(format!(
-                                    "{} hits {} with {} for {} damage!",
-                                    subject_name.0,
-                                    target_name.0,
-                                    weapon.get_name(),
-                                    damage,
-                                ));
-
-                                log.push(format!(
-                                    "(Rolled {}+{} (1d20 + AB) against {} AC ({}+{}+{}) for {} ({}d{}) damage)",
-                                    roll,
-                                    subject_creature_type.get_stats().attack_bonus,
-                                    total_ac,
-                                    target_creature_type.get_stats().armour_class,
-                                    armour.get_stats().armour_class,
-                                    shield.get_stats().armour_class,
-                                    damage,
-                                    &weapon_stats.die_num,
-                                    weapon_stats.die_size
-                                ));
-                            }
-                            _ => {
-                                log.push(format!(
-                                    "{} attacks {} but misses! ({}+{} attack roll against {} AC)",
-                                    subject_name.0,
-                                    target_name.0,
-                                    roll,
-                                    subject_creature_type.get_stats().attack_bonus,
-                                    total_ac,
-                                ));
+                        let shield = items.equipped_shield(target_equipped_shield);
+                        let armour = items.equipped_armour(target_equipped_armour);
+
+                        let roll = AttackRoll {
+                            roll: rng.gen_range(1..=20),
+                            attack_bonus: subject_stats.attack_bonus,
+                            armour_class: ArmourClass {
+                                base: target_stats.armour_class,
+                                armour: armour.armour_class,
+                                shield: shield.armour_class,
+                            },
+                        };
+
+                        if roll.is_hit() {
+                            let weapon = items.equipped_weapon(subject_equipped_weapon);
+                            let damage = weapon.get_damage(&mut rng);
+
+                            target_hp.0 -= damage;
+
+                            AttackOutcome::Hit {
+                                roll,
+                                weapon: weapon.clone(),
+                                damage,
+                                killed: target_hp.is_dead(),
                             }
+                        } else {
+                            AttackOutcome::Miss(roll)
                         }
                     }
-                    SeverityLevel::Min => {
-                        log.push(format!(
-                            "{} shouts a friendly greeting to {}",
-                            subject_name.0, target_name.0
-                        ));
-                    }
-                }
+                    SeverityLevel::Min => AttackOutcome::Greeting,
+                };
+
+                attack_event.send(AttackEvent {
+                    attacker: subject_entity,
+                    attacker_name: subject_name.0.clone(),
+                    target: target_entity,
+                    target_name: target_name.0.clone(),
+                    outcome,
+                });
             }
         }
     }
 }
 
+// Deaths are logged in the order creatures were spawned in when several die on the same tick
 pub fn death(
     mut commands: Commands,
     mut query: Query<(Entity, &Hp, &Name, &mut Render), Without<Dead>>,
-    mut log: ResMut<Vec<String>>,
+    mut death_event: EventWriter<DeathEvent>,
 ) {
-    for (entity, hp, name, mut render) in query.iter_mut() {
-        if hp.is_dead() {
-            render.char = "%".to_string();
-            // render.colour = Color::Red;
-            commands
-                .entity(entity)
-                .insert(Dead)
-                .remove::<Moves>()
-                .remove::<Aggression>();
-
-            log.push(format!("{} dies!", name.0));
-        }
+    let mut dead: Vec<(Entity, &Hp, &Name, Mut<Render>)> = query
+        .iter_mut()
+        .filter(|(_, hp, ..)| hp.is_dead())
+        .collect();
+    dead.sort_by_key(|(entity, ..)| *entity);
+
+    for (entity, _, name, mut render) in dead {
+        render.char = "%".to_string();
+        // render.colour = Color::Red;
+        commands
+            .entity(entity)
+            .insert(Dead)
+            .remove::<Moves>()
+            .remove::<Aggression>();
+
+        death_event.send(DeathEvent {
+            entity,
+            name: name.0.clone(),
+        });
     }
 }
 
-pub fn track_creature(
-    map: Res<Map>,
-    query: Query<
-        (
-            &Name,
-            &CreatureType,
-            &Hp,
-            Option<&EquippedWeapon>,
-            Option<&EquippedArmour>,
-        ),
-        With<Tracked>,
-    >,
-) {
+// What is shown about the tracked creature under the map
+type TrackedDetails<'a> = (
+    &'a Name,
+    &'a CreatureType,
+    &'a Hp,
+    Option<&'a EquippedWeapon>,
+    Option<&'a EquippedArmour>,
+);
+
+pub fn track_creature(dungeon: Res<Dungeon>, query: Query<TrackedDetails, With<Tracked>>) {
     if let Ok((name, creature_type, creature_hp, equipped_weapon, equipped_armour)) = query.single()
     {
         let equipped_weapon_name = match equipped_weapon {
-            Some(weapon) => weapon.0.get_name(),
+            Some(weapon) => weapon.0.to_string(),
             None => "-".to_string(),
         };
 
         let equipped_armour_name = match equipped_armour {
-            Some(armour) => armour.0.get_name(),
+            Some(armour) => armour.0.to_string(),
             None => "-".to_string(),
         };
 
+        let lines = [
+            format!("Name: {}", name.0),
+            format!("Type: {}", creature_type),
+            format!("Weapon: {}", equipped_weapon_name),
+            format!("Armour: {}", equipped_armour_name),
+            format!("Hp: {}", creature_hp.0),
+        ];
+
         let mut stdout = stdout();
 
         stdout
             .queue(style::SetForegroundColor(Color::White))
-            .unwrap()
-            .queue(cursor::MoveTo(0, (map.height + 1).try_into().unwrap()))
-            .unwrap()
-            .queue(style::Print(format!(
-                "\nName: {}                            \nType: {:?}                                        \nWeapon: {}                               \nArmour: {}                                  \nHp: {}                                            ",
-                name.0, creature_type, equipped_weapon_name, equipped_armour_name, creature_hp.0
-            )))
             .unwrap();
+
+        for (idx, line) in lines.iter().enumerate() {
+            stdout
+                .queue(cursor::MoveTo(
+                    0,
+                    (dungeon.height() + 2 + idx as i32).try_into().unwrap(),
+                ))
+                .unwrap()
+                .queue(style::Print(format!("{: <50}", line)))
+                .unwrap();
+        }
     }
 }
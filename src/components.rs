@@ -1,4 +1,3 @@
-
 pub struct Name(pub String);
 
 #[derive(PartialEq)]
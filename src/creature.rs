@@ -1,30 +1,112 @@
-#[derive(Clone, Debug, PartialEq, Hash, Eq)]
-pub enum CreatureType {
-    Human,
-    Goblin,
-    Orc,
+use std::{collections::HashSet, fmt, fs, path::Path};
+
+use crossterm::style::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::equipment::{Armour, ItemCatalogue, Shield, Weapon};
+
+// Creatures of the same type are on the same side
+#[derive(Clone, Debug, PartialEq, Hash, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CreatureType(pub String);
+
+impl fmt::Display for CreatureType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CombatStats {
     pub armour_class: i32,
     pub attack_bonus: i32,
+    // Added to the 1d20 initiative roll that decides who acts first each tick
+    pub initiative: i32,
+}
+
+/// One kind of creature from the creature data file. Scenarios pick templates by id.
+#[derive(Clone, Deserialize)]
+pub struct CreatureTemplate {
+    pub id: String,
+    pub creature_type: CreatureType,
+    // Any "{}" in the name is replaced with a number counting up from 1
+    pub name: String,
+    pub hp: i32,
+    pub glyph: String,
+    pub colour: Color,
+    pub vision_range: i32,
+    pub aggression: i32,
+    pub stats: CombatStats,
+    #[serde(default)]
+    pub weapon: Option<Weapon>,
+    #[serde(default)]
+    pub armour: Option<Armour>,
+    #[serde(default)]
+    pub shield: Option<Shield>,
+    // Show this creature's details under the map. Only the first tracked creature is shown.
+    #[serde(default)]
+    pub tracked: bool,
+}
+
+impl CreatureTemplate {
+    pub fn get_name(&self, number: u32) -> String {
+        self.name.replace("{}", &number.to_string())
+    }
 }
 
-impl CreatureType {
-    pub fn get_stats(&self) -> &CombatStats {
-        match self {
-            CreatureType::Human => &CombatStats {
-                armour_class: 10,
-                attack_bonus: 0,
-            },
-            CreatureType::Goblin => &CombatStats {
-                armour_class: 10,
-                attack_bonus: 0,
-            },
-            CreatureType::Orc => &CombatStats {
-                armour_class: 18,
-                attack_bonus: 0,
-            },
+// Equipment is checked against the item catalogue so a typo is caught before the battle starts
+pub fn load_creature_templates(
+    path: &Path,
+    items: &ItemCatalogue,
+) -> Result<Vec<CreatureTemplate>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+
+    let templates: Vec<CreatureTemplate> = ron::de::from_str(&contents)
+        .map_err(|error| format!("Could not parse {}: {}", path.display(), error))?;
+
+    let mut ids = HashSet::new();
+    for template in templates.iter() {
+        if !ids.insert(&template.id) {
+            return Err(format!(
+                "{}: there is more than one creature with the id {}",
+                path.display(),
+                template.id
+            ));
+        }
+
+        if template.glyph.chars().count() != 1 {
+            return Err(format!(
+                "{}: {} must have a single character glyph",
+                path.display(),
+                template.id
+            ));
+        }
+
+        if template.hp <= 0 {
+            return Err(format!(
+                "{}: {} must start with more than 0 hp",
+                path.display(),
+                template.id
+            ));
+        }
+
+        let unknown_item = match (&template.weapon, &template.armour, &template.shield) {
+            (Some(weapon), _, _) if !items.has_weapon(weapon) => Some(weapon.to_string()),
+            (_, Some(armour), _) if !items.has_armour(armour) => Some(armour.to_string()),
+            (_, _, Some(shield)) if !items.has_shield(shield) => Some(shield.to_string()),
+            _ => None,
+        };
+
+        if let Some(item) = unknown_item {
+            return Err(format!(
+                "{}: {} is equipped with {}, which is not in the item catalogue",
+                path.display(),
+                template.id,
+                item
+            ));
         }
     }
+
+    Ok(templates)
 }
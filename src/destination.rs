@@ -1,54 +1,113 @@
 use bevy::prelude::*;
-use rand::prelude::SliceRandom;
+use rand::{prelude::SliceRandom, Rng};
+use rand_chacha::ChaCha12Rng;
 
-use crate::{combat::{ Dead}, components::Name, creature::CreatureType, fov::Viewshed, map::Map, path::{Moves, Path}, position::{distance2d_pythagoras_squared, Position}};
+use crate::{
+    combat::{Aggression, Dead},
+    components::{Name, Severity, SeverityLevel},
+    creature::CreatureType,
+    equipment::{
+        Armour, EquippedArmour, EquippedShield, EquippedWeapon, Equips, ItemCatalogue, LooseItem,
+        Shield, Weapon,
+    },
+    flow::Flow,
+    fov::Viewshed,
+    map::{Dungeon, Level},
+    path::{Moves, Path},
+    position::{distance2d_pythagoras_squared, Position},
+};
+
+// One in this many wanderers heads for the stairs, on levels that have any
+const STAIRS_CHANCE: u32 = 4;
 
 pub struct Destination {
     pub position: Position,
+    // The creature being chased, if any. The destination moves with it until it dies or leaves
+    // the level.
+    pub target: Option<Entity>,
 }
 
+// Where a piece of gear lies, and what it is
+type Gear<'a> = (
+    &'a Position,
+    &'a Level,
+    Option<&'a Weapon>,
+    Option<&'a Armour>,
+    Option<&'a Shield>,
+);
+
+// A creature deciding where to go next, and what it wants on the way
+type Seeker<'a> = (
+    Entity,
+    &'a Name,
+    &'a Position,
+    &'a Level,
+    &'a CreatureType,
+    Option<&'a Destination>,
+    Option<&'a Viewshed>,
+    Option<&'a Flow>,
+    Option<&'a Aggression>,
+    Option<&'a Equips>,
+    (
+        Option<&'a EquippedWeapon>,
+        Option<&'a EquippedArmour>,
+        Option<&'a EquippedShield>,
+    ),
+);
+
 pub fn set_destination(
     mut commands: Commands,
-    subject_query: Query<
-        (
-            Entity,
-            &Name,
-            &Position,
-            &CreatureType,
-            Option<&Destination>,
-            Option<&Viewshed>,
-        ),
-        (With<Position>, With<Moves>),
-    >,
-    target_query: Query<(Entity, &Name, &Position, &CreatureType), Without<Dead>>,
-    map: Res<Map>,
+    subject_query: Query<Seeker, (With<Position>, With<Moves>)>,
+    target_query: Query<(Entity, &Name, &Position, &Level, &CreatureType), Without<Dead>>,
+    item_query: Query<Gear, LooseItem>,
+    items: Res<ItemCatalogue>,
+    dungeon: Res<Dungeon>,
+    mut rng: ResMut<ChaCha12Rng>,
     // mut log: ResMut<Vec<String>>,
 ) {
-    let mut rng = rand::thread_rng();
+    // Wanderers roll for where to go in the order they were spawned in, not the order they're
+    // stored in
+    let mut subjects: Vec<_> = subject_query.iter().collect();
+    subjects.sort_by_key(|(entity, ..)| *entity);
 
     'subject_loop: for (
         subject_entity,
         _subject_name,
         subject_position,
+        subject_level,
         subject_creature_type,
         subject_destination,
         subject_viewshed,
-    ) in subject_query.iter()
+        subject_flow,
+        subject_aggression,
+        subject_equips,
+        subject_equipped,
+    ) in subjects
     {
         if let Some(subject_viewshed) = subject_viewshed {
-            let mut closest_target: Option<&Position> = None;
+            let mut closest_target: Option<(Entity, &Position)> = None;
             let mut closest_distance: Option<f32> = None;
 
             // Only search if subject has a destination while wandering
             for point in subject_viewshed.visible_tiles.iter() {
-                for (target_entity, _target_name, target_position, target_creature_type) in
-                    target_query.iter()
+                for (
+                    target_entity,
+                    _target_name,
+                    target_position,
+                    target_level,
+                    target_creature_type,
+                ) in target_query.iter()
                 {
                     // Never set self as destination
                     if subject_entity == target_entity {
                         continue;
                     }
 
+                    // Creatures on other levels can't be seen
+                    if subject_level != target_level {
+                        continue;
+                    }
+
                     // Do not pursue creatures of the same type
                     if subject_creature_type == target_creature_type {
                         continue;
@@ -57,7 +116,7 @@ pub fn set_destination(
                     // If valid target in current viewsehd tile
                     if target_position.0 == point.x && target_position.1 == point.y {
                         let distance =
-                            distance2d_pythagoras_squared(&subject_position, &target_position);
+                            distance2d_pythagoras_squared(subject_position, target_position);
 
                         if let Some(closest_distance) = closest_distance {
                             if closest_distance < distance {
@@ -65,39 +124,121 @@ pub fn set_destination(
                             }
                         }
 
-                        closest_target = Some(&target_position);
+                        closest_target = Some((target_entity, target_position));
                         closest_distance = Some(distance);
                     }
                 }
             }
 
-            if let Some(closest_target) = closest_target {
-                // Check if new destination is the same as the old one, if not, don't change the path
-                if let Some(subject_destination) = subject_destination {
-                    if distance2d_pythagoras_squared(&subject_destination.position, &closest_target)
-                        == 0.0
-                    {
-                        continue 'subject_loop;
+            if let Some((target_entity, target_position)) = closest_target {
+                // Creatures without any aggression run from what they see rather than go up to it
+                let flow = match subject_aggression.map(|aggression| aggression.get_severity()) {
+                    Some(SeverityLevel::Min) => Flow::Flee,
+                    _ => Flow::Enemies,
+                };
+
+                if subject_flow != Some(&flow) {
+                    let mut subject = commands.entity(subject_entity);
+                    subject
+                        .insert(flow)
+                        .insert(Path::following(subject_position));
+
+                    // Where the chase carries on to if the target is lost sight of
+                    if flow == Flow::Enemies {
+                        subject.insert(Destination {
+                            position: target_position.clone(),
+                            target: Some(target_entity),
+                        });
+                    } else {
+                        subject.remove::<Destination>();
+                    }
+                } else if flow == Flow::Enemies {
+                    // Only move the destination when the target has moved or changed
+                    let moved = match subject_destination {
+                        Some(subject_destination) => {
+                            subject_destination.position != *target_position
+                                || subject_destination.target != Some(target_entity)
+                        }
+                        None => true,
+                    };
+                    if moved {
+                        commands.entity(subject_entity).insert(Destination {
+                            position: target_position.clone(),
+                            target: Some(target_entity),
+                        });
                     }
                 }
 
+                // [EXTRA DEBUG]
+                // log.push(format!("{} has a new destination", subject_name.0));
+                continue 'subject_loop;
+            }
+
+            // Creatures with nothing better to do go after gear they can see and would use,
+            // unless they're already by some
+            let sees_upgrade = subject_equips.is_some()
+                && item_query
+                    .iter()
+                    .any(|(position, level, weapon, armour, shield)| {
+                        level == subject_level
+                            && subject_viewshed
+                                .visible_tiles
+                                .iter()
+                                .any(|point| point.x == position.0 && point.y == position.1)
+                            && items.is_upgrade((weapon, armour, shield), subject_equipped)
+                    });
+            let by_item = item_query.iter().any(|(position, level, ..)| {
+                level == subject_level
+                    && distance2d_pythagoras_squared(subject_position, position) <= 2.0
+            });
+
+            if sees_upgrade && subject_flow == Some(&Flow::Items) {
+                continue 'subject_loop;
+            }
+            if sees_upgrade && subject_flow.is_none() && subject_destination.is_none() && !by_item {
                 commands
                     .entity(subject_entity)
-                    .insert(Destination {
-                        position: closest_target.clone(),
-                        // wandering: true,
-                    })
-                    .remove::<Path>();
+                    .insert(Flow::Items)
+                    .insert(Path::following(subject_position));
+                continue 'subject_loop;
+            }
+        }
 
-                // [EXTRA DEBUG]
-                // log.push(format!("{} has a new destination", subject_name.0));
+        // Out of sight of whatever the creature was following
+        if let Some(flow) = subject_flow {
+            let mut subject = commands.entity(subject_entity);
+            subject.remove::<Flow>().remove::<Path>();
+
+            if *flow == Flow::Enemies {
+                // Heads for where the target was last seen, and after it from there on. Setting the
+                // destination again has it pathed to.
+                if let Some(subject_destination) = subject_destination {
+                    subject.insert(Destination {
+                        position: subject_destination.position.clone(),
+                        target: subject_destination.target,
+                    });
+                    continue 'subject_loop;
+                }
             }
         }
+
         if subject_destination.is_none() {
-            let room = map.rooms.choose(&mut rng).unwrap();
-            let room_centre = room.center();
+            // Not the stairs the creature has just come out on
+            let stairs: Vec<Position> = dungeon
+                .stairs(subject_level)
+                .into_iter()
+                .filter(|stairs| stairs != subject_position)
+                .collect();
+
+            let position = if !stairs.is_empty() && rng.gen_range(0..STAIRS_CHANCE) == 0 {
+                stairs.choose(&mut *rng).unwrap().clone()
+            } else {
+                let room = dungeon.map(subject_level).rooms.choose(&mut *rng).unwrap();
+                room.center()
+            };
             commands.entity(subject_entity).insert(Destination {
-                position: Position(room_centre.0, room_centre.1),
+                position,
+                target: None,
             });
 
             // [DEBUG]
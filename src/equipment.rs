@@ -1,227 +1,349 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, fmt, fs, path::Path};
 
-use bevy::prelude::{Bundle, Commands, Entity, Query, ResMut, With};
+use bevy::prelude::{Bundle, Commands, Entity, EventWriter, Or, Query, Res, With, Without};
 use crossterm::style::Color;
 use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::{
+    combat::Hp,
     components::Name,
+    initiative::TurnOrder,
+    map::Level,
     position::{distance2d_pythagoras_squared, Position},
     render::Render,
 };
 
 pub struct Equips;
+
+// Query filter for gear lying on the ground rather than carried by a creature
+pub type LooseItem = (Or<(With<Weapon>, With<Armour>, With<Shield>)>, Without<Hp>);
 pub struct EquippedWeapon(pub Weapon);
 pub struct EquippedArmour(pub Armour);
 
 pub struct EquippedShield(pub Shield);
 
-#[derive(Clone, Debug)]
-pub enum Shield {
-    Unshielded,
-    Buckler,
+pub enum ItemAction {
+    PickedUp,
+    Dropped,
+    // Two handed weapons can't be picked up while holding a shield
+    BlockedByShield,
+    // Shields can't be picked up while holding a two handed weapon
+    BlockedByWeapon(Weapon),
+}
+
+pub struct ItemEvent {
+    pub creature: Entity,
+    pub creature_name: String,
+    pub item_name: String,
+    pub action: ItemAction,
 }
 
-#[derive(Clone, Debug)]
-pub enum Weapon {
-    Unarmed,
-    Sword,
-    Nunchucks,
-    GreatHammer,
+// Items are referred to by their name in the item catalogue
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Shield(pub String);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Weapon(pub String);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Armour(pub String);
+
+impl fmt::Display for Shield {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
-#[derive(Clone, Debug)]
-pub enum Armour {
-    Unarmoured,
-    ChainMail,
-    PlateMail,
+impl fmt::Display for Weapon {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
 }
 
+impl fmt::Display for Armour {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, Deserialize)]
 pub struct WeaponStats {
+    pub name: String,
+    pub glyph: String,
+    pub colour: Color,
     pub die_size: i32,
     pub die_num: i32,
     pub one_handed: bool,
+    // Relative chance of this weapon being picked for loot, unless a scenario's loot table says
+    #[serde(default)]
+    pub spawn_weight: u32,
 }
 
+// Shared by armour and shields
+#[derive(Clone, Deserialize)]
 pub struct ArmourStats {
+    pub name: String,
+    pub glyph: String,
+    pub colour: Color,
     pub armour_class: i32,
+    #[serde(default)]
+    pub spawn_weight: u32,
 }
 
 pub trait Power {
     fn get_power(&self) -> i32;
 }
 
-impl Power for Weapon {
+impl Power for WeaponStats {
     fn get_power(&self) -> i32 {
-        let stats = self.get_stats();
-        stats.die_num * stats.die_size
+        self.die_num * self.die_size
     }
 }
 
-impl Power for Armour {
+impl Power for ArmourStats {
     fn get_power(&self) -> i32 {
-        self.get_stats().armour_class
+        self.armour_class
     }
 }
 
-impl Power for Shield {
-    fn get_power(&self) -> i32 {
-        self.get_stats().armour_class
+impl WeaponStats {
+    pub fn get_damage(&self, rng: &mut ChaCha12Rng) -> i32 {
+        let mut damage = 0;
+
+        for _ in 0..self.die_num {
+            damage += rng.gen_range(1..=self.die_size);
+        }
+
+        damage
     }
 }
 
-impl Shield {
-    pub fn get_stats(&self) -> &ArmourStats {
-        match self {
-            &Shield::Unshielded => &ArmourStats { armour_class: 0 },
-            &Shield::Buckler => &ArmourStats { armour_class: 1 },
-        }
+/// Every weapon, armour and shield in the game, loaded from the item data file.
+#[derive(Clone, Deserialize)]
+pub struct ItemCatalogue {
+    // Stand-ins for creatures with nothing equipped in a slot
+    pub unarmed: WeaponStats,
+    pub unarmoured: ArmourStats,
+    pub unshielded: ArmourStats,
+    pub weapons: Vec<WeaponStats>,
+    pub armour: Vec<ArmourStats>,
+    pub shields: Vec<ArmourStats>,
+}
+
+impl ItemCatalogue {
+    // Item names are checked when the catalogue and anything referring to it are loaded, so
+    // these lookups fall back to the empty slot rather than failing mid game
+
+    pub fn get_weapon(&self, weapon: &Weapon) -> &WeaponStats {
+        self.weapons
+            .iter()
+            .find(|stats| stats.name == weapon.0)
+            .unwrap_or(&self.unarmed)
     }
 
-    pub fn get_name(&self) -> String {
-        format!("{:?}", self)
+    pub fn get_armour(&self, armour: &Armour) -> &ArmourStats {
+        self.armour
+            .iter()
+            .find(|stats| stats.name == armour.0)
+            .unwrap_or(&self.unarmoured)
     }
 
-    fn get_glyph(&self) -> String {
-        self.get_name()
-            .chars()
-            .next()
-            .unwrap()
-            .to_ascii_lowercase()
-            .to_string()
+    pub fn get_shield(&self, shield: &Shield) -> &ArmourStats {
+        self.shields
+            .iter()
+            .find(|stats| stats.name == shield.0)
+            .unwrap_or(&self.unshielded)
     }
 
-    pub fn get_bundle(&self) -> ShieldBundle {
-        ShieldBundle {
-            name: Name(self.get_name()),
-            render: Render {
-                colour: Color::DarkCyan,
-                char: self.get_glyph(),
-            },
-            shield: self.clone(),
+    pub fn equipped_weapon(&self, optional_equipped: Option<&EquippedWeapon>) -> &WeaponStats {
+        match optional_equipped {
+            Some(equipped) => self.get_weapon(&equipped.0),
+            None => &self.unarmed,
         }
     }
-}
 
-impl Armour {
-    pub fn get_stats(&self) -> &ArmourStats {
-        match self {
-            &Armour::Unarmoured => &ArmourStats { armour_class: 0 },
-            &Armour::ChainMail => &ArmourStats { armour_class: 1 },
-            &Armour::PlateMail => &ArmourStats { armour_class: 2 },
+    pub fn equipped_armour(&self, optional_equipped: Option<&EquippedArmour>) -> &ArmourStats {
+        match optional_equipped {
+            Some(equipped) => self.get_armour(&equipped.0),
+            None => &self.unarmoured,
         }
     }
 
-    pub fn get_name(&self) -> String {
-        format!("{:?}", self)
+    pub fn equipped_shield(&self, optional_equipped: Option<&EquippedShield>) -> &ArmourStats {
+        match optional_equipped {
+            Some(equipped) => self.get_shield(&equipped.0),
+            None => &self.unshielded,
+        }
     }
 
-    fn get_glyph(&self) -> String {
-        self.get_name()
-            .chars()
-            .next()
-            .unwrap()
-            .to_ascii_lowercase()
-            .to_string()
+    pub fn has_weapon(&self, weapon: &Weapon) -> bool {
+        self.weapons.iter().any(|stats| stats.name == weapon.0)
     }
 
-    pub fn get_bundle(&self) -> ArmourBundle {
-        ArmourBundle {
-            name: Name(self.get_name()),
-            render: Render {
-                colour: Color::Magenta,
-                char: self.get_glyph(),
-            },
-            armour: self.clone(),
-        }
+    pub fn has_armour(&self, armour: &Armour) -> bool {
+        self.armour.iter().any(|stats| stats.name == armour.0)
     }
-}
 
-impl Weapon {
-    pub fn get_stats(&self) -> &WeaponStats {
-        match self {
-            Weapon::Unarmed => &WeaponStats {
-                die_num: 1,
-                die_size: 3,
-                one_handed: true,
-            },
-            Weapon::Sword => &WeaponStats {
-                die_num: 1,
-                die_size: 6,
-                one_handed: true,
-            },
-            Weapon::Nunchucks => &WeaponStats {
-                die_num: 2,
-                die_size: 4,
-                one_handed: true,
-            },
-            Weapon::GreatHammer => &WeaponStats {
-                die_num: 1,
-                die_size: 10,
-                one_handed: false,
-            },
-        }
+    pub fn has_shield(&self, shield: &Shield) -> bool {
+        self.shields.iter().any(|stats| stats.name == shield.0)
     }
 
-    pub fn get_name(&self) -> String {
-        format!("{:?}", self)
-    }
+    /// Whether `pick_up_gear` would have a creature with the equipped gear take this item. Keep
+    /// this in sync with it.
+    pub fn is_upgrade(
+        &self,
+        (weapon, armour, shield): (Option<&Weapon>, Option<&Armour>, Option<&Shield>),
+        (equipped_weapon, equipped_armour, equipped_shield): (
+            Option<&EquippedWeapon>,
+            Option<&EquippedArmour>,
+            Option<&EquippedShield>,
+        ),
+    ) -> bool {
+        let current_weapon = self.equipped_weapon(equipped_weapon);
+
+        let better_weapon = match weapon {
+            Some(weapon) => {
+                let stats = self.get_weapon(weapon);
+                current_weapon.get_power() < stats.get_power()
+                    && (equipped_shield.is_none() || stats.one_handed)
+            }
+            None => false,
+        };
+        let better_armour = match armour {
+            Some(armour) => {
+                self.equipped_armour(equipped_armour).get_power()
+                    < self.get_armour(armour).get_power()
+            }
+            None => false,
+        };
+        let better_shield = match shield {
+            Some(shield) => {
+                self.equipped_shield(equipped_shield).get_power()
+                    < self.get_shield(shield).get_power()
+                    && current_weapon.one_handed
+            }
+            None => false,
+        };
 
-    fn get_glyph(&self) -> String {
-        self.get_name()
-            .chars()
-            .next()
-            .unwrap()
-            .to_ascii_lowercase()
-            .to_string()
+        better_weapon || better_armour || better_shield
     }
 
-    pub fn get_damage(&self) -> i32 {
-        let mut rng = rand::thread_rng();
-        let stats = self.get_stats();
-        let mut damage = 0;
-
-        for _ in 0..stats.die_num {
-            damage += rng.gen_range(1..=stats.die_size);
-        }
+    // Loot tables name items without saying what kind they are
+    pub fn has_item(&self, name: &str) -> bool {
+        self.has_weapon(&Weapon(name.to_string()))
+            || self.has_armour(&Armour(name.to_string()))
+            || self.has_shield(&Shield(name.to_string()))
+    }
 
-        damage
+    // The weight loot tables give an item when they don't set one of their own
+    pub fn spawn_weight(&self, name: &str) -> u32 {
+        self.weapons
+            .iter()
+            .map(|stats| (&stats.name, stats.spawn_weight))
+            .chain(
+                self.armour
+                    .iter()
+                    .map(|stats| (&stats.name, stats.spawn_weight)),
+            )
+            .chain(
+                self.shields
+                    .iter()
+                    .map(|stats| (&stats.name, stats.spawn_weight)),
+            )
+            .find(|(item, _)| *item == name)
+            .map_or(0, |(_, weight)| weight)
     }
 
-    pub fn get_bundle(&self) -> WeaponBundle {
+    pub fn weapon_bundle(&self, weapon: &Weapon) -> WeaponBundle {
+        let stats = self.get_weapon(weapon);
+
         WeaponBundle {
-            name: Name(self.get_name()),
+            name: Name(stats.name.clone()),
             render: Render {
-                colour: Color::DarkYellow,
-                char: self.get_glyph(),
+                colour: stats.colour,
+                char: stats.glyph.clone(),
             },
-            weapon: self.clone(),
+            weapon: weapon.clone(),
         }
     }
-}
 
-pub fn get_weapon(optional_equipped: Option<&EquippedWeapon>) -> &Weapon {
-    if let Some(equipped) = optional_equipped {
-        &equipped.0
-    } else {
-        &Weapon::Unarmed
+    pub fn armour_bundle(&self, armour: &Armour) -> ArmourBundle {
+        let stats = self.get_armour(armour);
+
+        ArmourBundle {
+            name: Name(stats.name.clone()),
+            render: Render {
+                colour: stats.colour,
+                char: stats.glyph.clone(),
+            },
+            armour: armour.clone(),
+        }
     }
-}
 
-pub fn get_armour(optional_equipped: Option<&EquippedArmour>) -> &Armour {
-    if let Some(equipped) = optional_equipped {
-        &equipped.0
-    } else {
-        &Armour::Unarmoured
+    pub fn shield_bundle(&self, shield: &Shield) -> ShieldBundle {
+        let stats = self.get_shield(shield);
+
+        ShieldBundle {
+            name: Name(stats.name.clone()),
+            render: Render {
+                colour: stats.colour,
+                char: stats.glyph.clone(),
+            },
+            shield: shield.clone(),
+        }
     }
 }
 
-pub fn get_shield(optional_equipped: Option<&EquippedShield>) -> &Shield {
-    if let Some(equipped) = optional_equipped {
-        &equipped.0
-    } else {
-        &Shield::Unshielded
+pub fn load_item_catalogue(path: &Path) -> Result<ItemCatalogue, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+
+    let catalogue: ItemCatalogue = ron::de::from_str(&contents)
+        .map_err(|error| format!("Could not parse {}: {}", path.display(), error))?;
+
+    let items = catalogue
+        .weapons
+        .iter()
+        .map(|stats| (&stats.name, &stats.glyph))
+        .chain(
+            catalogue
+                .armour
+                .iter()
+                .map(|stats| (&stats.name, &stats.glyph)),
+        )
+        .chain(
+            catalogue
+                .shields
+                .iter()
+                .map(|stats| (&stats.name, &stats.glyph)),
+        );
+
+    let mut names = HashSet::new();
+    for (name, glyph) in items {
+        if glyph.chars().count() != 1 {
+            return Err(format!(
+                "{}: {} must have a single character glyph",
+                path.display(),
+                name
+            ));
+        }
+
+        // Names have to be unique across every kind of item so a loot table can refer to any of them
+        if !names.insert(name) {
+            return Err(format!(
+                "{}: there is more than one item called {}",
+                path.display(),
+                name
+            ));
+        }
     }
+
+    Ok(catalogue)
 }
 
 #[derive(Bundle)]
@@ -245,68 +367,92 @@ pub struct ArmourBundle {
     armour: Armour,
 }
 
+// A creature that can pick gear up, and what it already has on
+type Wearer<'a> = (
+    Entity,
+    &'a Name,
+    &'a Position,
+    &'a Level,
+    Option<&'a EquippedWeapon>,
+    Option<&'a EquippedArmour>,
+    Option<&'a EquippedShield>,
+);
+
+// A piece of gear, wherever it is
+type Pickup<'a> = (
+    Entity,
+    &'a Name,
+    &'a Position,
+    &'a Level,
+    Option<&'a Weapon>,
+    Option<&'a Armour>,
+    Option<&'a Shield>,
+);
+
 pub fn pick_up_gear(
     mut commands: Commands,
-    subject_query: Query<
-        (
-            Entity,
-            &Name,
-            &Position,
-            Option<&EquippedWeapon>,
-            Option<&EquippedArmour>,
-            Option<&EquippedShield>,
-        ),
-        With<Equips>,
-    >,
-    target_query: Query<(
-        Entity,
-        &Name,
-        &Position,
-        Option<&Weapon>,
-        Option<&Armour>,
-        Option<&Shield>,
-    )>,
-    mut log: ResMut<Vec<String>>,
+    subject_query: Query<Wearer, With<Equips>>,
+    target_query: Query<Pickup>,
+    turn_order: Res<TurnOrder>,
+    items: Res<ItemCatalogue>,
+    mut item_event: EventWriter<ItemEvent>,
 ) {
-    // let mut rng = rand::thread_rng();
-
     let mut picked_up_entities: HashSet<Entity> = HashSet::new();
 
-    for (
-        subject_entity,
-        subject_name,
-        subject_position,
-        subject_equipped_weapon,
-        subject_equipped_armour,
-        subject_equipped_shield,
-    ) in subject_query.iter()
-    {
+    // Gear lying around is looked over tile by tile, whatever order it's stored in
+    let mut targets: Vec<_> = target_query.iter().collect();
+    targets.sort_by(
+        |(_, a_name, a_position, a_level, ..), (_, b_name, b_position, b_level, ..)| {
+            (a_level, a_position, &a_name.0).cmp(&(b_level, b_position, &b_name.0))
+        },
+    );
+
+    // Creatures earlier in the turn order get first pick of anything they're both standing by
+    for &subject_entity in turn_order.0.iter() {
+        let (
+            _,
+            subject_name,
+            subject_position,
+            subject_level,
+            subject_equipped_weapon,
+            subject_equipped_armour,
+            subject_equipped_shield,
+        ) = match subject_query.get(subject_entity) {
+            Ok(subject) => subject,
+            Err(_) => continue,
+        };
+
         for (
             target_entity,
             target_name,
             target_position,
+            target_level,
             target_weapon,
             target_armour,
             target_shield,
-        ) in target_query.iter()
+        ) in targets.iter().copied()
         {
-            if distance2d_pythagoras_squared(subject_position, target_position) <= 2.0 {
-                let equipped_weapon = get_weapon(subject_equipped_weapon);
+            if subject_level == target_level
+                && distance2d_pythagoras_squared(subject_position, target_position) <= 2.0
+            {
+                let equipped_weapon = items.equipped_weapon(subject_equipped_weapon);
 
-                let hands_full = !equipped_weapon.get_stats().one_handed;
+                let hands_full = !equipped_weapon.one_handed;
 
                 // Keep these in sync
                 if let Some(target_weapon) = target_weapon {
-                    if equipped_weapon.get_power() < target_weapon.get_power()
-                        && picked_up_entities.contains(&target_entity) == false
+                    if equipped_weapon.get_power() < items.get_weapon(target_weapon).get_power()
+                        && !picked_up_entities.contains(&target_entity)
                     {
                         if subject_equipped_shield.is_some()
-                            && !target_weapon.get_stats().one_handed
+                            && !items.get_weapon(target_weapon).one_handed
                         {
-                            log.push(format!(
-                                "{} cannot pick up {} because they are holding a shield",
-                                subject_name.0, target_name.0
-                            ));
+                            item_event.send(ItemEvent {
+                                creature: subject_entity,
+                                creature_name: subject_name.0.clone(),
+                                item_name: target_name.0.clone(),
+                                action: ItemAction::BlockedByShield,
+                            });
                         } else {
                             commands
                                 .entity(subject_entity)
@@ -319,29 +465,37 @@ pub fn pick_up_gear(
                             if let Some(subject_equipped_weapon) = subject_equipped_weapon {
                                 let dropped_weapon = &subject_equipped_weapon.0;
 
-                                let weapon_bundle = dropped_weapon.get_bundle();
+                                let weapon_bundle = items.weapon_bundle(dropped_weapon);
 
-                                log.push(format!(
-                                    "{} drops {}",
-                                    subject_name.0, &weapon_bundle.name.0
-                                ));
+                                item_event.send(ItemEvent {
+                                    creature: subject_entity,
+                                    creature_name: subject_name.0.clone(),
+                                    item_name: weapon_bundle.name.0.clone(),
+                                    action: ItemAction::Dropped,
+                                });
 
                                 commands
                                     .spawn()
                                     .insert_bundle(weapon_bundle)
-                                    .insert(subject_position.clone());
+                                    .insert(subject_position.clone())
+                                    .insert(*subject_level);
                             }
 
-                            log.push(format!("{} picks up {}", subject_name.0, target_name.0));
+                            item_event.send(ItemEvent {
+                                creature: subject_entity,
+                                creature_name: subject_name.0.clone(),
+                                item_name: target_name.0.clone(),
+                                action: ItemAction::PickedUp,
+                            });
                         }
                     }
                 }
 
                 // Keep these in sync
-                let equipped_armour = get_armour(subject_equipped_armour);
+                let equipped_armour = items.equipped_armour(subject_equipped_armour);
                 if let Some(target_armour) = target_armour {
-                    if equipped_armour.get_power() < target_armour.get_power()
-                        && picked_up_entities.contains(&target_entity) == false
+                    if equipped_armour.get_power() < items.get_armour(target_armour).get_power()
+                        && !picked_up_entities.contains(&target_entity)
                     {
                         commands
                             .entity(subject_entity)
@@ -354,34 +508,46 @@ pub fn pick_up_gear(
                         if let Some(subject_equipped_armour) = subject_equipped_armour {
                             let dropped_armour = &subject_equipped_armour.0;
 
-                            let armour_bundle = dropped_armour.get_bundle();
+                            let armour_bundle = items.armour_bundle(dropped_armour);
 
-                            log.push(format!(
-                                "{} drops {}",
-                                subject_name.0, &armour_bundle.name.0
-                            ));
+                            item_event.send(ItemEvent {
+                                creature: subject_entity,
+                                creature_name: subject_name.0.clone(),
+                                item_name: armour_bundle.name.0.clone(),
+                                action: ItemAction::Dropped,
+                            });
 
                             commands
                                 .spawn()
                                 .insert_bundle(armour_bundle)
-                                .insert(subject_position.clone());
+                                .insert(subject_position.clone())
+                                .insert(*subject_level);
                         }
 
-                        log.push(format!("{} picks up {}", subject_name.0, target_name.0));
+                        item_event.send(ItemEvent {
+                            creature: subject_entity,
+                            creature_name: subject_name.0.clone(),
+                            item_name: target_name.0.clone(),
+                            action: ItemAction::PickedUp,
+                        });
                     }
                 }
 
                 // Keep these in sync
-                let equipped_shield = get_shield(subject_equipped_shield);
+                let equipped_shield = items.equipped_shield(subject_equipped_shield);
                 if let Some(target_shield) = target_shield {
-                    if equipped_shield.get_power() < target_shield.get_power()
-                        && picked_up_entities.contains(&target_entity) == false
+                    if equipped_shield.get_power() < items.get_shield(target_shield).get_power()
+                        && !picked_up_entities.contains(&target_entity)
                     {
                         if hands_full {
-                            log.push(format!(
-                                "{} would like to pick up {} but is holding a {:?}",
-                                subject_name.0, target_name.0, equipped_weapon
-                            ));
+                            item_event.send(ItemEvent {
+                                creature: subject_entity,
+                                creature_name: subject_name.0.clone(),
+                                item_name: target_name.0.clone(),
+                                action: ItemAction::BlockedByWeapon(Weapon(
+                                    equipped_weapon.name.clone(),
+                                )),
+                            });
                         } else {
                             commands
                                 .entity(subject_entity)
@@ -394,20 +560,28 @@ pub fn pick_up_gear(
                             if let Some(subject_equipped_shield) = subject_equipped_shield {
                                 let dropped_shield = &subject_equipped_shield.0;
 
-                                let shield_bundle = dropped_shield.get_bundle();
+                                let shield_bundle = items.shield_bundle(dropped_shield);
 
-                                log.push(format!(
-                                    "{} drops {}",
-                                    subject_name.0, &shield_bundle.name.0
-                                ));
+                                item_event.send(ItemEvent {
+                                    creature: subject_entity,
+                                    creature_name: subject_name.0.clone(),
+                                    item_name: shield_bundle.name.0.clone(),
+                                    action: ItemAction::Dropped,
+                                });
 
                                 commands
                                     .spawn()
                                     .insert_bundle(shield_bundle)
-                                    .insert(subject_position.clone());
+                                    .insert(subject_position.clone())
+                                    .insert(*subject_level);
                             }
 
-                            log.push(format!("{} picks up {}", subject_name.0, target_name.0));
+                            item_event.send(ItemEvent {
+                                creature: subject_entity,
+                                creature_name: subject_name.0.clone(),
+                                item_name: target_name.0.clone(),
+                                action: ItemAction::PickedUp,
+                            });
                         }
                     }
                 }
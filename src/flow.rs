@@ -0,0 +1,377 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, BinaryHeap, HashMap},
+};
+
+use bevy::prelude::{Query, Res, ResMut};
+use rand::prelude::SliceRandom;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    combat::Living,
+    creature::CreatureType,
+    equipment::LooseItem,
+    map::{Dungeon, Level, Map},
+    path::{neighbours, Movement},
+    position::Position,
+    scenario::Scenario,
+};
+
+// What tiles nothing can be reached from are marked with
+const UNREACHABLE: i32 = i32::MAX;
+
+/// Which of the shared flow fields a creature is following downhill, in place of a path of its
+/// own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Flow {
+    // Towards the nearest creature of another type
+    Enemies,
+    // Away from creatures of other types, towards wherever is safest rather than just further
+    Flee,
+    // Towards the nearest loose item
+    Items,
+}
+
+/// How far every tile of a level is from the nearest of some set of tiles, in the same units
+/// paths are costed in. A creature heads for the nearest of them by stepping downhill.
+#[derive(Clone, Default)]
+pub struct DijkstraMap {
+    pub distances: Vec<i32>,
+}
+
+impl DijkstraMap {
+    pub fn new(map: &Map, movement: Movement, sources: &[usize]) -> DijkstraMap {
+        let mut distances = vec![UNREACHABLE; map.tiles.len()];
+        for &idx in sources {
+            distances[idx] = 0;
+        }
+
+        DijkstraMap::spread(map, movement, distances)
+    }
+
+    /// A map for running away from whatever this one leads to. Scaling the distances by more than
+    /// -1 before spreading them again makes creatures head for open space further off rather than
+    /// straight into the nearest corner.
+    pub fn flee(&self, map: &Map, movement: Movement) -> DijkstraMap {
+        let distances = self
+            .distances
+            .iter()
+            .map(|&distance| {
+                if distance == UNREACHABLE {
+                    UNREACHABLE
+                } else {
+                    distance * -6 / 5
+                }
+            })
+            .collect();
+
+        DijkstraMap::spread(map, movement, distances)
+    }
+
+    // Works outwards from every tile that already has a distance, cheapest first, until each tile
+    // has the cheapest distance it can get from a neighbour
+    fn spread(map: &Map, movement: Movement, mut distances: Vec<i32>) -> DijkstraMap {
+        let mut open: BinaryHeap<Reverse<(i32, usize)>> = distances
+            .iter()
+            .enumerate()
+            .filter(|(_, distance)| **distance != UNREACHABLE)
+            .map(|(idx, distance)| Reverse((*distance, idx)))
+            .collect();
+
+        while let Some(Reverse((distance, idx))) = open.pop() {
+            if distance > distances[idx] {
+                continue;
+            }
+
+            // Stepping from a neighbour onto this tile costs this tile's ground
+            let cost = map.path_cost(idx);
+            let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+            for ((x, y), step) in neighbours(map, movement, x, y, |_, _| cost) {
+                let neighbour = map.xy_idx(x, y);
+                if distance + step < distances[neighbour] {
+                    distances[neighbour] = distance + step;
+                    open.push(Reverse((distance + step, neighbour)));
+                }
+            }
+        }
+
+        DijkstraMap { distances }
+    }
+
+    /// The neighbouring tile that takes a creature at (x, y) furthest downhill, or `None` once
+    /// there is nowhere lower to go. Tiles with a creature on are only picked when there's no free
+    /// one. Ties are settled at random, as two creatures heading for each other would otherwise
+    /// mirror each other's steps and never meet.
+    pub fn downhill(
+        &self,
+        map: &Map,
+        movement: Movement,
+        x: i32,
+        y: i32,
+        rng: &mut ChaCha12Rng,
+    ) -> Option<(i32, i32)> {
+        let here = self.distances.get(map.xy_idx(x, y)).copied()?;
+        if here == UNREACHABLE {
+            return None;
+        }
+
+        let lower: Vec<((i32, i32), (bool, i32))> = neighbours(map, movement, x, y, |_, _| 0)
+            .into_iter()
+            .map(|(spot, _)| {
+                let idx = map.xy_idx(spot.0, spot.1);
+                (spot, (map.occupant(idx).is_some(), self.distances[idx]))
+            })
+            .filter(|&(_, (_, distance))| distance < here)
+            .collect();
+
+        let best = lower.iter().map(|&(_, key)| key).min()?;
+        let steps: Vec<(i32, i32)> = lower
+            .into_iter()
+            .filter(|&(_, key)| key == best)
+            .map(|(spot, _)| spot)
+            .collect();
+
+        steps.choose(rng).copied()
+    }
+}
+
+/// The flow fields of one level, shared by every creature on it. Worked out again every tick, so
+/// never saved.
+#[derive(Default)]
+pub struct FlowFields {
+    // Keyed by the creature type the enemies are enemies of
+    pub enemies: HashMap<CreatureType, DijkstraMap>,
+    pub flee: HashMap<CreatureType, DijkstraMap>,
+    pub items: DijkstraMap,
+}
+
+impl FlowFields {
+    pub fn get(&self, flow: Flow, creature_type: &CreatureType) -> Option<&DijkstraMap> {
+        match flow {
+            Flow::Enemies => self.enemies.get(creature_type),
+            Flow::Flee => self.flee.get(creature_type),
+            Flow::Items => Some(&self.items),
+        }
+    }
+}
+
+// Runs once at the start of every tick, so however many creatures follow a field it is only
+// worked out once. Fields nobody is following are left empty.
+pub fn update_flow_fields(
+    creature_query: Query<(&Position, &Level, &CreatureType, Option<&Flow>), Living>,
+    item_query: Query<(&Position, &Level), LooseItem>,
+    scenario: Res<Scenario>,
+    mut dungeon: ResMut<Dungeon>,
+) {
+    let movement = scenario.movement;
+    let followed: BTreeSet<(Level, Flow, &CreatureType)> = creature_query
+        .iter()
+        .filter_map(|(_, level, creature_type, flow)| {
+            flow.map(|flow| (*level, *flow, creature_type))
+        })
+        .collect();
+
+    for (level, map) in dungeon.levels.iter_mut().enumerate() {
+        let level = Level(level);
+        let on_level = |position: &Position, other_level: &Level| {
+            (*other_level == level).then(|| map.xy_idx(position.0, position.1))
+        };
+
+        let mut flow = FlowFields::default();
+
+        for &(_, followed_flow, creature_type) in followed.iter().filter(|(on, ..)| *on == level) {
+            if followed_flow == Flow::Items {
+                if !flow.items.distances.is_empty() {
+                    continue;
+                }
+                let items: Vec<usize> = item_query
+                    .iter()
+                    .filter_map(|(position, item_level)| on_level(position, item_level))
+                    .collect();
+                flow.items = DijkstraMap::new(map, movement, &items);
+                continue;
+            }
+
+            if !flow.enemies.contains_key(creature_type) {
+                let enemies: Vec<usize> = creature_query
+                    .iter()
+                    .filter(|(_, _, other_type, _)| *other_type != creature_type)
+                    .filter_map(|(position, other_level, ..)| on_level(position, other_level))
+                    .collect();
+                flow.enemies.insert(
+                    creature_type.clone(),
+                    DijkstraMap::new(map, movement, &enemies),
+                );
+            }
+
+            if followed_flow == Flow::Flee {
+                let flee = flow.enemies[creature_type].flee(map, movement);
+                flow.flee.insert(creature_type.clone(), flee);
+            }
+        }
+
+        map.flow = flow;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        app::Events,
+        prelude::{Entity, IntoSystem, Stage, SystemStage, World},
+    };
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::{
+        combat::Hp,
+        initiative::TurnOrder,
+        map::char_to_tile,
+        path::{move_path, update_occupancy, DoorEvent, Moves, Path},
+    };
+
+    fn map_from(rows: &[&str]) -> Map {
+        let mut map = Map::new(rows[0].len() as i32, rows.len() as i32);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, glyph) in row.chars().enumerate() {
+                let idx = map.xy_idx(x as i32, y as i32);
+                map.tiles[idx] = char_to_tile(glyph).unwrap();
+            }
+        }
+        map.occupants = vec![None; map.tiles.len()];
+        map
+    }
+
+    fn distance(field: &DijkstraMap, map: &Map, x: i32, y: i32) -> i32 {
+        field.distances[map.xy_idx(x, y)]
+    }
+
+    #[test]
+    fn distances_grow_away_from_the_sources() {
+        let map = map_from(&["#########", "#...#...#", "#########"]);
+        let field = DijkstraMap::new(&map, Movement::Orthogonal, &[map.xy_idx(1, 1)]);
+
+        let row: Vec<i32> = (1..4).map(|x| distance(&field, &map, x, 1)).collect();
+        assert_eq!(row, vec![0, 1, 2]);
+        // Walled off from the source
+        assert_eq!(distance(&field, &map, 5, 1), UNREACHABLE);
+    }
+
+    #[test]
+    fn downhill_leads_to_the_sources_and_fleeing_leads_away() {
+        let map = map_from(&["#########", "#.......#", "#########"]);
+        let field = DijkstraMap::new(&map, Movement::Orthogonal, &[map.xy_idx(1, 1)]);
+        let mut rng = ChaCha12Rng::seed_from_u64(0);
+
+        let towards = field.downhill(&map, Movement::Orthogonal, 4, 1, &mut rng);
+        assert_eq!(towards, Some((3, 1)));
+        assert_eq!(
+            field.downhill(&map, Movement::Orthogonal, 1, 1, &mut rng),
+            None
+        );
+
+        let flee = field.flee(&map, Movement::Orthogonal);
+        let away = flee.downhill(&map, Movement::Orthogonal, 4, 1, &mut rng);
+        assert_eq!(away, Some((5, 1)));
+    }
+
+    #[test]
+    fn downhill_steps_round_other_creatures_when_it_can() {
+        let mut map = map_from(&["######", "#....#", "#....#", "######"]);
+        let field = DijkstraMap::new(&map, Movement::Orthogonal, &[map.xy_idx(4, 2)]);
+        let blocked = map.xy_idx(2, 1);
+        map.occupants[blocked] = Some(Entity::new(0));
+
+        for seed in 0..5 {
+            let mut rng = ChaCha12Rng::seed_from_u64(seed);
+            let step = field.downhill(&map, Movement::Orthogonal, 1, 1, &mut rng);
+            assert_eq!(step, Some((1, 2)));
+        }
+    }
+
+    #[test]
+    fn fields_are_only_worked_out_for_the_flows_being_followed() {
+        let mut world = World::default();
+        world.insert_resource(Dungeon {
+            levels: vec![map_from(&["#######", "#.....#", "#######"])],
+        });
+        world.insert_resource(
+            ron::de::from_str::<Scenario>(include_str!("../assets/scenarios/default.ron")).unwrap(),
+        );
+        world.spawn().insert_bundle((
+            Hp(10),
+            CreatureType("human".to_string()),
+            Position(1, 1),
+            Level(0),
+            Flow::Enemies,
+        ));
+        world.spawn().insert_bundle((
+            Hp(10),
+            CreatureType("goblin".to_string()),
+            Position(5, 1),
+            Level(0),
+        ));
+
+        SystemStage::single_threaded()
+            .with_system(update_flow_fields.system())
+            .run(&mut world);
+
+        let map = world.get_resource::<Dungeon>().unwrap().map(&Level(0));
+        let human = CreatureType("human".to_string());
+        let goblin = CreatureType("goblin".to_string());
+        let enemies = map.flow.get(Flow::Enemies, &human).unwrap();
+        assert_eq!(distance(enemies, map, 5, 1), 0);
+        assert_eq!(distance(enemies, map, 1, 1), 4);
+        assert!(map.flow.get(Flow::Enemies, &goblin).is_none());
+        assert!(map.flow.get(Flow::Flee, &human).is_none());
+        assert!(map.flow.items.distances.is_empty());
+    }
+
+    #[test]
+    fn creatures_following_a_field_walk_up_to_what_it_leads_to() {
+        let mut world = World::default();
+        world.insert_resource(Dungeon {
+            levels: vec![map_from(&["########", "#......#", "########"])],
+        });
+        world.insert_resource(
+            ron::de::from_str::<Scenario>(include_str!("../assets/scenarios/default.ron")).unwrap(),
+        );
+        world.insert_resource(ChaCha12Rng::seed_from_u64(0));
+        world.insert_resource(Events::<DoorEvent>::default());
+        let chaser = world
+            .spawn()
+            .insert_bundle((
+                Moves,
+                Hp(10),
+                CreatureType("human".to_string()),
+                Position(1, 1),
+                Level(0),
+                Flow::Enemies,
+                Path::following(&Position(1, 1)),
+            ))
+            .id();
+        world.spawn().insert_bundle((
+            Hp(10),
+            CreatureType("goblin".to_string()),
+            Position(6, 1),
+            Level(0),
+        ));
+        world.insert_resource(TurnOrder(vec![chaser]));
+
+        for _ in 0..6 {
+            SystemStage::single_threaded()
+                .with_system(update_occupancy.system())
+                .run(&mut world);
+            SystemStage::single_threaded()
+                .with_system(update_flow_fields.system())
+                .run(&mut world);
+            SystemStage::single_threaded()
+                .with_system(move_path.system())
+                .run(&mut world);
+        }
+
+        // It stops next to the goblin rather than on top of it
+        assert_eq!(world.get::<Position>(chaser), Some(&Position(5, 1)));
+    }
+}
@@ -1,12 +1,14 @@
 use std::{convert::TryInto, io::stdout};
 
-use bevy::prelude::{Changed, Query, Res};
+use bevy::prelude::{ChangeTrackers, EventReader, Query, Res};
 use crossterm::{cursor, style, QueueableCommand};
 use rltk::{field_of_view, Point};
 
 use crate::{
-    map::{tile_to_char, Map},
+    map::{tile_to_char, Dungeon, Level},
+    path::DoorEvent,
     position::Position,
+    render::ShownLevel,
 };
 
 pub struct Viewshed {
@@ -14,13 +16,22 @@ pub struct Viewshed {
     pub range: i32,
 }
 
-pub fn draw_viewshed(viewshed_query: Query<&Viewshed>, map: Res<Map>) {
+pub fn draw_viewshed(
+    viewshed_query: Query<(&Viewshed, &Level)>,
+    dungeon: Res<Dungeon>,
+    shown_level: Res<ShownLevel>,
+) {
     let mut stdout = stdout();
     stdout
         .queue(style::SetForegroundColor(style::Color::Yellow))
         .unwrap();
 
-    for viewshed in viewshed_query.iter() {
+    let map = dungeon.map(&shown_level.0);
+    for (viewshed, level) in viewshed_query.iter() {
+        if *level != shown_level.0 {
+            continue;
+        }
+
         for point in viewshed.visible_tiles.iter() {
             let tile = map.tiles[map.xy_idx(point.x, point.y)];
 
@@ -37,13 +48,24 @@ pub fn draw_viewshed(viewshed_query: Query<&Viewshed>, map: Res<Map>) {
 }
 
 pub fn calculate_viewshed(
-    mut viewshed_query: Query<(&mut Viewshed, &Position), Changed<Position>>,
-    map: Res<Map>,
+    mut viewshed_query: Query<(&mut Viewshed, &Position, &Level, ChangeTrackers<Position>)>,
+    dungeon: Res<Dungeon>,
+    mut door_event: EventReader<DoorEvent>,
 ) {
-    for (mut viewshed, position) in viewshed_query.iter_mut() {
+    let door_levels: Vec<Level> = door_event.iter().map(|event| event.level).collect();
+
+    for (mut viewshed, position, level, position_tracker) in viewshed_query.iter_mut() {
+        // Everyone looks again when a door on their level opens or closes, as well as when they
+        // move
+        if !position_tracker.is_changed() && !door_levels.contains(level) {
+            continue;
+        }
+
+        let map = dungeon.map(level);
+
         viewshed.visible_tiles.clear();
         viewshed.visible_tiles =
-            field_of_view(Point::new(position.0, position.1), viewshed.range, &*map);
+            field_of_view(Point::new(position.0, position.1), viewshed.range, map);
         viewshed
             .visible_tiles
             .retain(|p| p.x >= 0 && p.x < map.width && p.y >= 0 && p.y < map.height);
@@ -0,0 +1,35 @@
+use std::cmp::Reverse;
+
+use bevy::prelude::{Entity, Query, ResMut};
+use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+
+use crate::{combat::Living, creature::CombatStats};
+
+// Living creatures in the order they act this tick, highest initiative first
+#[derive(Default)]
+pub struct TurnOrder(pub Vec<Entity>);
+
+/// Rolls 1d20 plus each creature's initiative bonus at the start of every tick. Ties are broken
+/// with another roll so the order never falls back on entity spawn order. Creatures roll in the
+/// order they were spawned in, which a game loaded from a save keeps.
+pub fn roll_initiative(
+    query: Query<(Entity, &CombatStats), Living>,
+    mut turn_order: ResMut<TurnOrder>,
+    mut rng: ResMut<ChaCha12Rng>,
+) {
+    let mut creatures: Vec<(Entity, &CombatStats)> = query.iter().collect();
+    creatures.sort_by_key(|&(entity, _)| entity);
+
+    let mut rolls: Vec<(i32, u32, Entity)> = creatures
+        .into_iter()
+        .map(|(entity, stats)| {
+            let initiative = rng.gen_range(1..=20) + stats.initiative;
+            (initiative, rng.gen(), entity)
+        })
+        .collect();
+
+    rolls.sort_by_key(|&(initiative, tie_breaker, _)| (Reverse(initiative), tie_breaker));
+
+    turn_order.0 = rolls.into_iter().map(|(_, _, entity)| entity).collect();
+}
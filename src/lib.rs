@@ -4,17 +4,27 @@ mod components;
 mod creature;
 mod destination;
 mod equipment;
+mod flow;
 mod fov;
+mod initiative;
 mod log;
 mod map;
+mod output;
 mod path;
 mod position;
 mod rect;
 mod render;
+mod replay;
+mod save;
+mod scenario;
 mod spawner;
+mod stairs;
+mod terrain;
+mod tournament;
 
 use std::{
     io::{stdout, Write},
+    path::{Path, PathBuf},
     time::{Duration, Instant},
 };
 
@@ -22,32 +32,72 @@ use bevy::{
     app::{ScheduleRunnerPlugin, ScheduleRunnerSettings},
     ecs::schedule::ReportExecutionOrderAmbiguities,
     log::LogPlugin,
-    prelude::{App, IntoSystem, ParallelSystemDescriptorCoercion},
+    prelude::{
+        App, AppBuilder, CoreStage, IntoSystem, ParallelSystemDescriptorCoercion, ResMut,
+        StartupStage,
+    },
 };
 
 use combat::{death, fight, track_creature};
 use crossterm::{cursor, style::ResetColor, QueueableCommand};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
 
+use flow::update_flow_fields;
 use fov::{calculate_viewshed, draw_viewshed};
-use map::{draw_map, Map};
+use initiative::{roll_initiative, TurnOrder};
+use map::{draw_map, Dungeon};
 
-use path::{move_path, path_to_destination};
+use path::{close_doors, move_path, path_to_destination, repath, update_occupancy, DoorEvent};
 use position::assign_positions;
-use render::draw_entities;
+use render::{draw_entities, pick_shown_level, ShownLevel};
+use replay::{
+    check_replay, load_replay, playback_runner, print_replay_result, record_replay, Replay,
+    ReplayPlayback, ReplayRecorder, REPLAY_VERSION,
+};
+use save::{load_game, restore_entities, save_game, SaveGame};
 
-use log::draw_log;
+use log::{draw_log, log_attacks, log_burns, log_deaths, log_game_over, log_items, log_stairs};
 
 use crate::{
-    cleanup::{creature_type_count, end_game},
+    cleanup::{creature_type_count, end_game, print_result},
+    creature::{load_creature_templates, CreatureTemplate, CreatureType},
     destination::set_destination,
-    equipment::pick_up_gear,
+    equipment::{load_item_catalogue, pick_up_gear, ItemCatalogue},
+    scenario::{load_scenario, Scenario},
     spawner::spawn_all,
+    stairs::take_stairs,
+    terrain::burn_in_lava,
+    tournament::{add_result_systems, print_game_result},
 };
 
+// Game events, for anything that wants to react to what happens in a battle
+pub use combat::{ArmourClass, AttackEvent, AttackOutcome, AttackRoll, DeathEvent};
+pub use equipment::{ItemAction, ItemEvent, Weapon, WeaponStats};
+pub use map::MapGenerator;
+pub use output::OutputFormat;
+pub use save::SaveSettings;
+pub use scenario::DEFAULT_SCENARIO_PATH;
+pub use stairs::StairsEvent;
+pub use terrain::BurnEvent;
+pub use tournament::run_tournament;
+
 #[derive(Default)]
 pub struct TickCount(pub i32);
 
-pub struct EndGameEvent;
+// The seed a new game was generated from. Games continued from a save don't have one.
+pub struct Seed(pub u64);
+
+pub struct EndGameEvent {
+    // None when every creature died, or time ran out with no side ahead
+    pub winner: Option<CreatureType>,
+    // True when the scenario's tick limit ended the game
+    pub out_of_time: bool,
+}
+
+fn count_ticks(mut tick_count: ResMut<TickCount>) {
+    tick_count.0 += 1;
+}
 
 fn flush_stdout() {
     let mut stdout = stdout();
@@ -60,109 +110,400 @@ fn flush_stdout() {
         .unwrap();
 }
 
+pub struct GameSettings {
+    pub seed: Option<u64>,
+    // The scenario file describing the battle
+    pub scenario: PathBuf,
+    // These replace what the scenario file says
+    pub map_generator: Option<MapGenerator>,
+    pub tick_ms: Option<u64>,
+    pub tracked: Option<String>,
+    // Runs the full simulation as fast as possible without drawing anything
+    pub headless: bool,
+    // How headless games and tournaments print their results
+    pub format: OutputFormat,
+    // Continue a saved game instead of starting a new one
+    pub load: Option<PathBuf>,
+    pub save: Option<SaveSettings>,
+    // Record the battle to a replay file, written when the game ends
+    pub record: Option<PathBuf>,
+    // Play back a recorded battle, checking it still plays out the same way
+    pub replay: Option<PathBuf>,
+}
+
+// Where the data files describing creatures and items are read from
+const CREATURES_PATH: &str = "assets/creatures.ron";
+const ITEMS_PATH: &str = "assets/items.ron";
+
+// Everything loaded from the data files before a battle starts
+#[derive(Clone)]
+struct GameData {
+    scenario: Scenario,
+    creatures: Vec<CreatureTemplate>,
+    items: ItemCatalogue,
+}
+
+impl GameData {
+    // Loads the data files and applies any scenario settings given on the command line
+    fn load(settings: &GameSettings) -> Result<GameData, String> {
+        let items = load_item_catalogue(Path::new(ITEMS_PATH))?;
+        let creatures = load_creature_templates(Path::new(CREATURES_PATH), &items)?;
+        let mut scenario = load_scenario(&settings.scenario, &creatures, &items)?;
+
+        if let Some(generator) = &settings.map_generator {
+            scenario.map.generator = generator.clone();
+            scenario.map.validate()?;
+        }
+        if let Some(tick_ms) = settings.tick_ms {
+            scenario.tick_ms = tick_ms;
+        }
+        if let Some(tracked) = &settings.tracked {
+            scenario.tracked = Some(tracked.clone());
+            scenario.check_tracked(&creatures)?;
+        }
+
+        Ok(GameData {
+            scenario,
+            creatures,
+            items,
+        })
+    }
+}
+
+// How a battle starts: freshly generated from a seed, or restored from a save file
+enum GameSetup {
+    New { seed: u64 },
+    Load(Box<SaveGame>, Dungeon),
+}
+
 // Our Bevy app's entry point
-pub fn run() {
-    print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
+// Passing the seed printed by a previous run replays that battle exactly
+pub fn run(settings: GameSettings) -> Result<(), String> {
+    if settings.load.is_some() && (settings.record.is_some() || settings.replay.is_some()) {
+        return Err(
+            "Replays start from a seed, so a loaded game can't be recorded or replayed".to_string(),
+        );
+    }
+
+    let data = GameData::load(&settings)?;
+
+    let replay = match &settings.replay {
+        Some(path) => Some(load_replay(path)?),
+        None => None,
+    };
+
+    if let Some(replay) = &replay {
+        if replay.scenario != data.scenario.name {
+            return Err(format!(
+                "The replay was recorded with the \"{}\" scenario but \"{}\" is selected",
+                replay.scenario, data.scenario.name
+            ));
+        }
+    }
+
+    let setup = match &settings.load {
+        Some(path) => {
+            let (save, dungeon) = load_game(path, &data.items)?;
+            GameSetup::Load(Box::new(save), dungeon)
+        }
+        None => GameSetup::New {
+            seed: match &replay {
+                Some(replay) => replay.seed,
+                None => settings.seed.unwrap_or_else(rand::random),
+            },
+        },
+    };
+
+    if settings.headless {
+        // Structured results include the seed themselves
+        if settings.format == OutputFormat::Text {
+            match &setup {
+                GameSetup::New { seed } => println!("Seed: {}", seed),
+                GameSetup::Load(save, _) => println!("Loaded game at tick {}", save.tick_count),
+            }
+        }
+    } else {
+        print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
+    }
+
+    let seed = match &setup {
+        GameSetup::New { seed } => Some(*seed),
+        GameSetup::Load(..) => None,
+    };
+    let scenario_name = data.scenario.name.clone();
+
+    let mut app = build_app(setup, data, settings.headless)?;
 
-    let map: Map = Map::new_map_rooms_and_corridors();
-    let log: Vec<String> = Vec::new();
+    // Plugins are just a grouped set of app builder calls (just like we're doing here).
+    // We could easily turn our game into a plugin, but you can check out the plugin example for
+    // that :)
+    app.add_plugin(LogPlugin);
+
+    if let Some(save_settings) = settings.save {
+        app.insert_resource(save_settings)
+            .add_system_to_stage(CoreStage::PostUpdate, save_game.system());
+    }
+
+    if let (Some(path), Some(seed)) = (settings.record, seed) {
+        app.insert_resource(ReplayRecorder {
+            path,
+            replay: Replay {
+                version: REPLAY_VERSION,
+                seed,
+                scenario: scenario_name,
+                spawns: Vec::new(),
+                ticks: Vec::new(),
+            },
+        })
+        .add_system_to_stage(CoreStage::PostUpdate, record_replay.system());
+    }
+
+    let playing_back = replay.is_some();
+    if let Some(replay) = replay {
+        app.insert_resource(ReplayPlayback {
+            replay,
+            diverged_at: None,
+        })
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            check_replay.system().label("check_replay"),
+        );
+
+        if settings.headless {
+            app.add_system_to_stage(
+                CoreStage::PostUpdate,
+                print_replay_result.system().after("check_replay"),
+            );
+        } else {
+            // Playback paces the ticks itself so it can pause, step and change speed
+            app.set_runner(playback_runner);
+        }
+    }
+
+    if settings.headless {
+        if !playing_back {
+            match settings.format {
+                OutputFormat::Text => {
+                    app.add_system(print_result.system().after("log"));
+                }
+                format => {
+                    add_result_systems(&mut app);
+                    app.insert_resource(format)
+                        .add_system_to_stage(CoreStage::PostUpdate, print_game_result.system());
+                }
+            }
+        }
+    } else {
+        add_render_systems(&mut app);
+    }
+
+    // This call to run() starts the app we just built!
+    app.run();
+
+    Ok(())
+}
+
+// Builds a game with everything except the terminal output. The log plugin is left to the caller
+// because it can only be added to one app per process. Fails when the scenario's map settings
+// can't make a map.
+fn build_app(setup: GameSetup, data: GameData, headless: bool) -> Result<AppBuilder, String> {
+    // Some systems are configured by adding their settings as a resource
+    let runner_settings = if headless {
+        // Loop without waiting between ticks
+        ScheduleRunnerSettings::default()
+    } else {
+        ScheduleRunnerSettings::run_loop(Duration::from_millis(data.scenario.tick_ms))
+    };
 
     // Bevy apps are created using the builder pattern. We use the builder to add systems,
     // resources, and plugins to our app
-    App::build()
+    let mut app = App::build();
+
+    match setup {
+        GameSetup::New { seed } => {
+            let mut rng = ChaCha12Rng::seed_from_u64(seed);
+            let (dungeon, reports) = Dungeon::generate(
+                &data.scenario.map,
+                &data.scenario.starting_creatures(),
+                &mut rng,
+            )?;
+            let mut log: Vec<String> = vec![format!("Seed: {}", seed)];
+            for (level, report) in reports.iter().enumerate() {
+                for line in report.describe() {
+                    // Which level a line is about only needs saying when there's more than one
+                    log.push(match reports.len() {
+                        1 => line,
+                        _ => format!("Level {}: {}", level + 1, line),
+                    });
+                }
+            }
+
+            app.insert_resource(TickCount(0))
+                .insert_resource(Seed(seed))
+                .insert_resource(log)
+                .insert_resource(dungeon)
+                // Every random roll in the game is drawn from this one generator
+                .insert_resource(rng)
+                // Startup systems
+                .add_startup_system(spawn_all.system());
+        }
+        GameSetup::Load(mut save, dungeon) => {
+            let log: Vec<String> = std::mem::take(&mut save.log);
+
+            app.insert_resource(TickCount(save.tick_count))
+                .insert_resource(log)
+                .insert_resource(dungeon)
+                .insert_resource(save.rng.clone())
+                .insert_resource(*save)
+                .add_startup_system(restore_entities.system());
+        }
+    }
+
+    app.add_event::<AttackEvent>()
+        .add_event::<DeathEvent>()
+        .add_event::<ItemEvent>()
+        .add_event::<StairsEvent>()
+        .add_event::<BurnEvent>()
+        .add_event::<DoorEvent>()
         .add_event::<EndGameEvent>()
         .insert_resource(Instant::now())
-        .insert_resource(TickCount(0))
-        .insert_resource(log)
-        .insert_resource(map)
-        // Some systems are configured by adding their settings as a resource
-        .insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_millis(300)))
+        .insert_resource(data.scenario)
+        .insert_resource(data.creatures)
+        .insert_resource(data.items)
+        .insert_resource(TurnOrder::default())
+        .insert_resource(runner_settings)
         .insert_resource(ReportExecutionOrderAmbiguities)
-        // Plugins are just a grouped set of app builder calls (just like we're doing here).
-        // We could easily turn our game into a plugin, but you can check out the plugin example for
-        // that :) The plugin below runs our app's "system schedule" once every 5 seconds
-        // (configured above).
-        .add_plugin(LogPlugin::default())
-        .add_plugin(ScheduleRunnerPlugin::default())
-        // Resources that implement the Default or FromResources trait can be added like this:
-        // .init_resource::<GameState>()
-        // Startup systems run exactly once BEFORE all other systems. These are generally used for
-        // app initialization code (ex: adding entities and resources)
-        // .add_startup_system(startup_system.system())
-        // my_system calls converts normal rust functions into ECS systems:
-        // .add_system(print_message_system.system())
-        // SYSTEM EXECUTION ORDER
-        //
-        // Each system belongs to a `Stage`, which controls the execution strategy and broad order
-        // of the systems within each tick. Startup stages (which startup systems are
-        // registered in) will always complete before ordinary stages begin,
-        // and every system in a stage must complete before the next stage advances.
-        // Once every stage has concluded, the main loop is complete and begins again.
-        //
-        // By default, all systems run in parallel, except when they require mutable access to a
-        // piece of data. This is efficient, but sometimes order matters.
-        // For example, we want our "game over" system to execute after all other systems to ensure
-        // we don't accidentally run the game for an extra round.
-        //
-        // Rather than splitting each of your systems into separate stages, you should force an
-        // explicit ordering between them by giving the relevant systems a label with
-        // `.label`, then using the `.before` or `.after` methods. Systems will not be
-        // scheduled until all of the systems that they have an "ordering dependency" on have
-        // completed.
-        //
-        // Doing that will, in just about all cases, lead to better performance compared to
-        // splitting systems between stages, because it gives the scheduling algorithm more
-        // opportunities to run systems in parallel.
-        // Stages are still necessary, however: end of a stage is a hard sync point
-        // (meaning, no systems are running) where `Commands` issued by systems are processed.
-        // This is required because commands can perform operations that are incompatible with
-        // having systems in flight, such as spawning or deleting entities,
-        // adding or removing resources, etc.
-        //
-        // add_system(system) adds systems to the UPDATE stage by default
-        // However we can manually specify the stage if we want to. The following is equivalent to
-        // add_system(score_system)
-        // .add_system_to_stage(CoreStage::Update, score_system.system())
-        // We can also create new stages. Here is what our games stage order will look like:
-        // "before_round": new_player_system, new_round_system
-        // "update": print_message_system, score_system
-        // "after_round": score_check_system, game_over_system
-        // .add_stage_before(
-        //     CoreStage::Update,
-        //     MyStage::BeforeRound,
-        //     SystemStage::parallel(),
-        // )
-        // .add_stage_after(
-        //     CoreStage::Update,
-        //     MyStage::AfterRound,
-        //     SystemStage::parallel(),
-        // )
-        // .add_system_to_stage(MyStage::BeforeRound, new_round_system.system())
-        // .add_system_to_stage(MyStage::BeforeRound, new_player_system.system())
-        // // We can ensure that game_over system runs after score_check_system using explicit ordering
-        // // constraints First, we label the system we want to refer to using `.label`
-        // // Then, we use either `.before` or `.after` to describe the order we want the relationship
-        // .add_system_to_stage(
-        //     MyStage::AfterRound,
-        //     score_check_system.system().label(MyLabels::ScoreCheck),
-        // )
-        // .add_system_to_stage(
-        //     MyStage::AfterRound,
-        //     game_over_system.system().after(MyLabels::ScoreCheck),
-        // )
-        // We can check our systems for execution order ambiguities by examining the output produced
-        // in the console by using the `LogPlugin` and adding the following Resource to our App :)
-        // Be aware that not everything reported by this checker is a potential problem, you'll have
-        // to make that judgement yourself.
-        // This call to run() starts the app we just built!
-        // Startup systems
-        .add_startup_system(spawn_all.system())
+        // The plugin below runs our app's "system schedule" once every tick (configured above).
+        .add_plugin(ScheduleRunnerPlugin::default());
+
+    // Resources that implement the Default or FromResources trait can be added like this:
+    // .init_resource::<GameState>()
+    // Startup systems run exactly once BEFORE all other systems. These are generally used for
+    // app initialization code (ex: adding entities and resources)
+    // .add_startup_system(startup_system.system())
+    // my_system calls converts normal rust functions into ECS systems:
+    // .add_system(print_message_system.system())
+    // SYSTEM EXECUTION ORDER
+    //
+    // Each system belongs to a `Stage`, which controls the execution strategy and broad order
+    // of the systems within each tick. Startup stages (which startup systems are
+    // registered in) will always complete before ordinary stages begin,
+    // and every system in a stage must complete before the next stage advances.
+    // Once every stage has concluded, the main loop is complete and begins again.
+    //
+    // By default, all systems run in parallel, except when they require mutable access to a
+    // piece of data. This is efficient, but sometimes order matters.
+    // For example, we want our "game over" system to execute after all other systems to ensure
+    // we don't accidentally run the game for an extra round.
+    //
+    // Rather than splitting each of your systems into separate stages, you should force an
+    // explicit ordering between them by giving the relevant systems a label with
+    // `.label`, then using the `.before` or `.after` methods. Systems will not be
+    // scheduled until all of the systems that they have an "ordering dependency" on have
+    // completed.
+    //
+    // Doing that will, in just about all cases, lead to better performance compared to
+    // splitting systems between stages, because it gives the scheduling algorithm more
+    // opportunities to run systems in parallel.
+    // Stages are still necessary, however: end of a stage is a hard sync point
+    // (meaning, no systems are running) where `Commands` issued by systems are processed.
+    // This is required because commands can perform operations that are incompatible with
+    // having systems in flight, such as spawning or deleting entities,
+    // adding or removing resources, etc.
+    //
+    // add_system(system) adds systems to the UPDATE stage by default
+    // However we can manually specify the stage if we want to. The following is equivalent to
+    // add_system(score_system)
+    // .add_system_to_stage(CoreStage::Update, score_system.system())
+    // We can also create new stages. Here is what our games stage order will look like:
+    // "before_round": new_player_system, new_round_system
+    // "update": print_message_system, score_system
+    // "after_round": score_check_system, game_over_system
+    // .add_stage_before(
+    //     CoreStage::Update,
+    //     MyStage::BeforeRound,
+    //     SystemStage::parallel(),
+    // )
+    // .add_stage_after(
+    //     CoreStage::Update,
+    //     MyStage::AfterRound,
+    //     SystemStage::parallel(),
+    // )
+    // .add_system_to_stage(MyStage::BeforeRound, new_round_system.system())
+    // .add_system_to_stage(MyStage::BeforeRound, new_player_system.system())
+    // // We can ensure that game_over system runs after score_check_system using explicit ordering
+    // // constraints First, we label the system we want to refer to using `.label`
+    // // Then, we use either `.before` or `.after` to describe the order we want the relationship
+    // .add_system_to_stage(
+    //     MyStage::AfterRound,
+    //     score_check_system.system().label(MyLabels::ScoreCheck),
+    // )
+    // .add_system_to_stage(
+    //     MyStage::AfterRound,
+    //     game_over_system.system().after(MyLabels::ScoreCheck),
+    // )
+    // We can check our systems for execution order ambiguities by examining the output produced
+    // in the console by using the `LogPlugin` and adding the following Resource to our App :)
+    // Be aware that not everything reported by this checker is a potential problem, you'll have
+    // to make that judgement yourself.
+    add_simulation_systems(&mut app);
+
+    Ok(app)
+}
+
+// Everything needed to play out a battle. None of these systems draw to the terminal.
+fn add_simulation_systems(app: &mut AppBuilder) {
+    app
         // .add_startup_system(spawn_goblins.system())
         // .add_startup_system(spawn_food.system())
+        // Creatures are put in place once they're spawned, so they're all there for the first tick
+        .add_startup_system_to_stage(
+            StartupStage::PostStartup,
+            update_occupancy.system().label("update_occupancy"),
+        )
+        .add_startup_system_to_stage(
+            StartupStage::PostStartup,
+            assign_positions.system().after("update_occupancy"),
+        )
+        .add_system(
+            count_ticks
+                .system()
+                .label("count_ticks")
+                .before("initialize"),
+        )
+        .add_system(
+            update_occupancy
+                .system()
+                .label("update_occupancy")
+                .before("initialize"),
+        )
+        .add_system(
+            update_flow_fields
+                .system()
+                .label("update_flow_fields")
+                .after("update_occupancy")
+                .before("initialize"),
+        )
+        .add_system(
+            roll_initiative
+                .system()
+                .label("roll_initiative")
+                .after("initialize"),
+        )
         // initialize
-        .add_system(assign_positions.system().label("initialize"))
-        .add_system(path_to_destination.system().label("initialize"))
-        .add_system(fight.system().label("fight").after("initialize"))
+        .add_system(
+            assign_positions
+                .system()
+                .label("assign_positions")
+                .label("initialize"),
+        )
+        .add_system(
+            path_to_destination
+                .system()
+                .label("initialize")
+                .after("assign_positions"),
+        )
+        .add_system(fight.system().label("fight").after("roll_initiative"))
         .add_system(pick_up_gear.system().label("pick_up_gear").after("fight"))
         .add_system(
             set_destination
@@ -171,15 +512,102 @@ pub fn run() {
                 .after("pick_up_gear"),
         )
         // move
-        .add_system(move_path.system().label("move").after("set_destination"))
+        .add_system(repath.system().label("repath").after("set_destination"))
+        .add_system(move_path.system().label("move").after("repath"))
+        .add_system(take_stairs.system().label("take_stairs").after("move"))
+        .add_system(
+            close_doors
+                .system()
+                .label("close_doors")
+                .after("take_stairs"),
+        )
+        .add_system(
+            burn_in_lava
+                .system()
+                .label("burn_in_lava")
+                .after("close_doors"),
+        )
         .add_system(
             calculate_viewshed
                 .system()
                 .label("calculate_viewshed")
-                .after("move"),
+                .after("close_doors"),
+        )
+        // cleanup_entities
+        .add_system(
+            death
+                .system()
+                .label("cleanup_entities")
+                .after("calculate_viewshed")
+                .after("burn_in_lava"),
+        )
+        .add_system(
+            creature_type_count
+                .system()
+                .label("creature_type_count")
+                .after("cleanup_entities"),
+        )
+        // log, kept in the order things happened during the tick
+        .add_system(
+            log_attacks
+                .system()
+                .label("log_attacks")
+                .label("log")
+                .after("creature_type_count"),
+        )
+        .add_system(
+            log_items
+                .system()
+                .label("log_items")
+                .label("log")
+                .after("log_attacks"),
+        )
+        .add_system(
+            log_stairs
+                .system()
+                .label("log_stairs")
+                .label("log")
+                .after("log_items"),
+        )
+        .add_system(
+            log_burns
+                .system()
+                .label("log_burns")
+                .label("log")
+                .after("log_stairs"),
+        )
+        .add_system(
+            log_deaths
+                .system()
+                .label("log_deaths")
+                .label("log")
+                .after("log_burns"),
+        )
+        .add_system(log_game_over.system().label("log").after("log_deaths"))
+        .add_system(
+            end_game
+                .system()
+                .label("end_game")
+                .after("creature_type_count"),
+        );
+}
+
+// Terminal output. Each draw system slots in around the simulation systems above.
+fn add_render_systems(app: &mut AppBuilder) {
+    app.init_resource::<ShownLevel>()
+        .add_system(
+            pick_shown_level
+                .system()
+                .label("pick_shown_level")
+                .after("close_doors"),
         )
         // draw map
-        .add_system(draw_map.system().label("draw_map").after("move"))
+        .add_system(
+            draw_map
+                .system()
+                .label("draw_map")
+                .after("pick_shown_level"),
+        )
         // draw_viewshed
         .add_system(
             draw_viewshed
@@ -193,35 +621,22 @@ pub fn run() {
             draw_entities
                 .system()
                 .label("draw_entities")
-                .after("draw_viewshed"),
-        )
-        // cleanup_entities
-        .add_system(
-            death
-                .system()
-                .label("cleanup_entities")
-                .after("draw_entities"),
-        )
-        .add_system(
-            draw_log
-                .system()
-                .label("draw_log")
-                .after("creature_type_count"),
+                .after("draw_viewshed")
+                .before("cleanup_entities"),
         )
+        .add_system(draw_log.system().label("draw_log").after("log"))
         // flush_stdout
         .add_system(
             flush_stdout
                 .system()
                 .label("flush_stdout")
-                .after("draw_log"),
+                .after("draw_log")
+                .before("end_game"),
         )
         .add_system(
-            creature_type_count
+            track_creature
                 .system()
-                .label("creature_type_count")
-                .after("cleanup_entities"),
-        )
-        .add_system(end_game.system().after("flush_stdout").label("end_game"))
-        .add_system(track_creature.system().label("track_creature").after("flush_stdout"))
-        .run();
+                .label("track_creature")
+                .after("flush_stdout"),
+        );
 }
@@ -1,15 +1,154 @@
-use std::{convert::TryInto, io::stdout};
+use std::{convert::TryInto, io::stdout, time::Instant};
 
-use bevy::prelude::Res;
+use bevy::prelude::{EventReader, Res, ResMut};
 use crossterm::{
     cursor,
     style::{self, Color},
     QueueableCommand,
 };
 
-use crate::map::Map;
+use crate::{
+    combat::{AttackEvent, AttackOutcome, DeathEvent},
+    equipment::{ItemAction, ItemEvent},
+    map::Dungeon,
+    stairs::StairsEvent,
+    terrain::BurnEvent,
+    EndGameEvent,
+};
+
+// The log is one consumer of the game events, turning each of them into a readable line
+
+pub fn describe_attack(event: &AttackEvent) -> Vec<String> {
+    match &event.outcome {
+        AttackOutcome::Greeting => vec![format!(
+            "{} shouts a friendly greeting to {}",
+            event.attacker_name, event.target_name
+        )],
+        AttackOutcome::Miss(roll) => vec![format!(
+            "{} attacks {} but misses! ({}+{} attack roll against {} AC)",
+            event.attacker_name,
+            event.target_name,
+            roll.roll,
+            roll.attack_bonus,
+            roll.armour_class.total(),
+        )],
+        AttackOutcome::Hit {
+            roll,
+            weapon,
+            damage,
+            ..
+        } => {
+            vec![
+                format!(
+                    "{} hits {} with {} for {} damage!",
+                    event.attacker_name, event.target_name, weapon.name, damage,
+                ),
+                format!(
+                    "(Rolled {}+{} (1d20 + AB) against {} AC ({}+{}+{}) for {} ({}d{}) damage)",
+                    roll.roll,
+                    roll.attack_bonus,
+                    roll.armour_class.total(),
+                    roll.armour_class.base,
+                    roll.armour_class.armour,
+                    roll.armour_class.shield,
+                    damage,
+                    weapon.die_num,
+                    weapon.die_size
+                ),
+            ]
+        }
+    }
+}
+
+pub fn describe_item(event: &ItemEvent) -> String {
+    match &event.action {
+        ItemAction::PickedUp => format!("{} picks up {}", event.creature_name, event.item_name),
+        ItemAction::Dropped => format!("{} drops {}", event.creature_name, event.item_name),
+        ItemAction::BlockedByShield => format!(
+            "{} cannot pick up {} because they are holding a shield",
+            event.creature_name, event.item_name
+        ),
+        ItemAction::BlockedByWeapon(weapon) => format!(
+            "{} would like to pick up {} but is holding a {}",
+            event.creature_name, event.item_name, weapon
+        ),
+    }
+}
+
+pub fn describe_stairs(event: &StairsEvent) -> String {
+    let direction = if event.to.0 > event.from.0 {
+        "down"
+    } else {
+        "up"
+    };
+    format!(
+        "{} goes {} the stairs to level {}",
+        event.name, direction, event.to
+    )
+}
+
+pub fn describe_burn(event: &BurnEvent) -> String {
+    format!(
+        "{} is burned by lava for {} damage!",
+        event.name, event.damage
+    )
+}
+
+pub fn describe_death(event: &DeathEvent) -> String {
+    format!("{} dies!", event.name)
+}
+
+pub fn log_attacks(mut log: ResMut<Vec<String>>, mut attack_event: EventReader<AttackEvent>) {
+    for event in attack_event.iter() {
+        log.extend(describe_attack(event));
+    }
+}
 
-pub fn draw_log(map: Res<Map>, log: Res<Vec<String>>) {
+pub fn log_items(mut log: ResMut<Vec<String>>, mut item_event: EventReader<ItemEvent>) {
+    for event in item_event.iter() {
+        log.push(describe_item(event));
+    }
+}
+
+pub fn log_stairs(mut log: ResMut<Vec<String>>, mut stairs_event: EventReader<StairsEvent>) {
+    for event in stairs_event.iter() {
+        log.push(describe_stairs(event));
+    }
+}
+
+pub fn log_burns(mut log: ResMut<Vec<String>>, mut burn_event: EventReader<BurnEvent>) {
+    for event in burn_event.iter() {
+        log.push(describe_burn(event));
+    }
+}
+
+pub fn log_deaths(mut log: ResMut<Vec<String>>, mut death_event: EventReader<DeathEvent>) {
+    for event in death_event.iter() {
+        log.push(describe_death(event));
+    }
+}
+
+pub fn log_game_over(
+    mut log: ResMut<Vec<String>>,
+    mut end_game_event: EventReader<EndGameEvent>,
+    game_start_time: Res<Instant>,
+) {
+    for event in end_game_event.iter() {
+        match (&event.winner, event.out_of_time) {
+            (Some(winner), _) => log.push(format!(
+                "Game over!  Winner: {}s after {} seconds",
+                winner,
+                game_start_time.elapsed().as_secs()
+            )),
+            (None, true) => log.push("Game over!  Out of time with no side ahead!".to_string()),
+            (None, false) => {
+                log.push("Game over!  Everybody is dead!  Everybody loses!".to_string())
+            }
+        }
+    }
+}
+
+pub fn draw_log(dungeon: Res<Dungeon>, log: Res<Vec<String>>) {
     let mut stdout = stdout();
 
     stdout
@@ -19,17 +158,21 @@ pub fn draw_log(map: Res<Map>, log: Res<Vec<String>>) {
     'log_loop: for (idx, log_entry) in log.iter().rev().enumerate() {
         stdout
             .queue(cursor::MoveTo(
-                (map.width + 1).try_into().unwrap(),
+                (dungeon.width() + 1).try_into().unwrap(),
                 (idx + 2).try_into().unwrap(),
             ))
             // .unwrap()
             // .queue(style::Print(log_entry))
             .unwrap()
-            .queue(style::Print(format!("{: <1$}", log_entry, 145 - log_entry.len())))
+            .queue(style::Print(format!(
+                "{: <1$}",
+                log_entry,
+                145 - log_entry.len()
+            )))
             .unwrap();
 
-            if idx >= (map.height - 3).try_into().unwrap() {
-                break 'log_loop;
-            }
+        if idx >= (dungeon.height() - 3).try_into().unwrap() {
+            break 'log_loop;
+        }
     }
 }
@@ -1,3 +1,151 @@
+use std::{path::PathBuf, process};
+
+use bevy_game::{GameSettings, MapGenerator, OutputFormat, SaveSettings, DEFAULT_SCENARIO_PATH};
+use structopt::StructOpt;
+
+/// A terminal roguelike battle simulator. Creatures from rival factions wander a generated
+/// dungeon, pick up gear and fight until one side is left standing.
+#[derive(StructOpt)]
+#[structopt(name = "bevy-game")]
+struct Cli {
+    /// Seed for the battle, or for the whole tournament with --games. Passing the seed printed by
+    /// a previous run replays that battle exactly.
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    /// Scenario file describing the map, creatures, loot and victory conditions
+    #[structopt(long, default_value = DEFAULT_SCENARIO_PATH)]
+    scenario: PathBuf,
+
+    /// Map generator to use instead of the scenario's, with its default settings
+    /// [possible values: rooms-and-corridors, bsp, caves, drunkards-walk, dla]
+    #[structopt(long)]
+    map_generator: Option<MapGenerator>,
+
+    /// Milliseconds per tick when drawing in the terminal, instead of the scenario's
+    #[structopt(long)]
+    tick_ms: Option<u64>,
+
+    /// Run as fast as possible without drawing anything, then print the result
+    #[structopt(long)]
+    headless: bool,
+
+    /// Play this many headless games and print statistics for all of them
+    #[structopt(long)]
+    games: Option<u32>,
+
+    /// How headless games and tournaments print their results [possible values: text, json, ron]
+    #[structopt(long, default_value = "text")]
+    format: OutputFormat,
+
+    /// Name of the creature to show details of under the map, such as "Goblin2"
+    #[structopt(long)]
+    track: Option<String>,
+
+    /// Continue a game from a save file
+    #[structopt(long)]
+    load: Option<PathBuf>,
+
+    /// Save the game to this file when it ends, or at --save-at
+    #[structopt(long)]
+    save: Option<PathBuf>,
+
+    /// Tick to save the game on instead of waiting for the end
+    #[structopt(long)]
+    save_at: Option<i32>,
+
+    /// Record the battle to a replay file
+    #[structopt(long)]
+    record: Option<PathBuf>,
+
+    /// Play back a replay file, checking it still plays out the same way
+    #[structopt(long)]
+    replay: Option<PathBuf>,
+}
+
+impl Cli {
+    // Catches combinations of options that can't work together
+    fn validate(&self) -> Result<(), String> {
+        if self.save_at.is_some() && self.save.is_none() {
+            return Err("--save-at needs --save to say where to save the game".to_string());
+        }
+
+        if self.tick_ms == Some(0) {
+            return Err("--tick-ms must be more than 0".to_string());
+        }
+
+        if self.load.is_some() && (self.record.is_some() || self.replay.is_some()) {
+            return Err(
+                "Replays start from a seed, so a loaded game can't be recorded or replayed"
+                    .to_string(),
+            );
+        }
+
+        if self.record.is_some() && self.replay.is_some() {
+            return Err("--record and --replay can't be used together".to_string());
+        }
+
+        if self.replay.is_some() && self.seed.is_some() {
+            return Err(
+                "Replays use the seed they were recorded with, so --seed can't be used".to_string(),
+            );
+        }
+
+        if let Some(games) = self.games {
+            if games == 0 {
+                return Err("--games needs at least one game".to_string());
+            }
+
+            let single_game_options = self.load.is_some()
+                || self.save.is_some()
+                || self.record.is_some()
+                || self.replay.is_some();
+            if single_game_options {
+                return Err(
+                    "--games can't be used with --load, --save, --record or --replay".to_string(),
+                );
+            }
+        } else if self.format != OutputFormat::Text && (!self.headless || self.replay.is_some()) {
+            return Err("--format only applies to --headless games and --games".to_string());
+        }
+
+        Ok(())
+    }
+}
+
 fn main() {
-    bevy_game::run();
+    let cli = Cli::from_args();
+
+    if let Err(error) = cli.validate() {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
+
+    let save_at = cli.save_at;
+    let settings = GameSettings {
+        seed: cli.seed,
+        scenario: cli.scenario,
+        map_generator: cli.map_generator,
+        tick_ms: cli.tick_ms,
+        tracked: cli.track,
+        headless: cli.headless,
+        format: cli.format,
+        load: cli.load,
+        save: cli.save.map(|path| SaveSettings {
+            path,
+            at_tick: save_at,
+        }),
+        record: cli.record,
+        replay: cli.replay,
+    };
+
+    let result = match cli.games {
+        Some(games) => bevy_game::run_tournament(games, settings),
+        None => bevy_game::run(settings),
+    };
+
+    if let Err(error) = result {
+        eprintln!("error: {}", error);
+        process::exit(1);
+    }
 }
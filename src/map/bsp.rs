@@ -0,0 +1,139 @@
+use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+
+use super::{builder::MapBuilder, Map};
+use crate::rect::Rect;
+
+/// Binary space partition. The map is split in two, then each half is split again and so on until
+/// the pieces are too small to split. Every piece gets one room, so rooms never overlap, and the
+/// two halves of every split are joined by a corridor so every room can be reached from every
+/// other.
+pub struct BspBuilder {
+    // Pieces are never split smaller than this, in tiles across
+    pub min_leaf_size: i32,
+    pub min_room_size: i32,
+}
+
+impl MapBuilder for BspBuilder {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng) {
+        // Rooms are dug inside the edges of their piece, so this leaves a wall around the map
+        let whole_map = Rect::new(0, 0, map.width - 1, map.height - 1);
+        map.build_bsp_leaf(&whole_map, self.min_leaf_size, self.min_room_size, rng);
+    }
+}
+
+impl Map {
+    // Splits this piece of the map if it's big enough, otherwise digs a room in it. Returns one of
+    // the rooms inside the piece for the corridor joining it to its other half.
+    fn build_bsp_leaf(
+        &mut self,
+        leaf: &Rect,
+        min_leaf_size: i32,
+        min_room_size: i32,
+        rng: &mut ChaCha12Rng,
+    ) -> Rect {
+        let width = leaf.x2 - leaf.x1;
+        let height = leaf.y2 - leaf.y1;
+
+        let can_split_across = width >= min_leaf_size * 2;
+        let can_split_down = height >= min_leaf_size * 2;
+
+        // Long thin pieces are split across their length to keep the rooms from getting stretched
+        let split_across = match (can_split_across, can_split_down) {
+            (true, true) if width > height => true,
+            (true, true) if height > width => false,
+            (true, true) => rng.gen_range(0..2) == 1,
+            (true, false) => true,
+            (false, true) => false,
+            (false, false) => return self.dig_bsp_room(leaf, min_room_size, rng),
+        };
+
+        let (first, second) = if split_across {
+            let split = rng.gen_range(min_leaf_size..=width - min_leaf_size);
+            (
+                Rect::new(leaf.x1, leaf.y1, split, height),
+                Rect::new(leaf.x1 + split, leaf.y1, width - split, height),
+            )
+        } else {
+            let split = rng.gen_range(min_leaf_size..=height - min_leaf_size);
+            (
+                Rect::new(leaf.x1, leaf.y1, width, split),
+                Rect::new(leaf.x1, leaf.y1 + split, width, height - split),
+            )
+        };
+
+        let first_room = self.build_bsp_leaf(&first, min_leaf_size, min_room_size, rng);
+        let second_room = self.build_bsp_leaf(&second, min_leaf_size, min_room_size, rng);
+        self.join_rooms(&first_room, &second_room, rng);
+
+        if rng.gen_range(0..2) == 1 {
+            first_room
+        } else {
+            second_room
+        }
+    }
+
+    // Digs a randomly sized room somewhere inside the piece, leaving a wall on its far edges so
+    // rooms in neighbouring pieces don't run into each other
+    fn dig_bsp_room(&mut self, leaf: &Rect, min_room_size: i32, rng: &mut ChaCha12Rng) -> Rect {
+        let leaf_width = leaf.x2 - leaf.x1;
+        let leaf_height = leaf.y2 - leaf.y1;
+
+        let w = rng.gen_range(min_room_size..leaf_width);
+        let h = rng.gen_range(min_room_size..leaf_height);
+        let x = rng.gen_range(leaf.x1..leaf.x2 - w);
+        let y = rng.gen_range(leaf.y1..leaf.y2 - h);
+
+        let room = Rect::new(x, y, w, h);
+        self.apply_room_to_map(&room);
+        self.rooms.push(room.clone());
+
+        room
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::checks::{edge_is_wall, floor_area_count};
+
+    fn bsp_map(seed: u64) -> Map {
+        let mut map = Map::new(60, 40);
+        BspBuilder {
+            min_leaf_size: 10,
+            min_room_size: 4,
+        }
+        .build(&mut map, &mut ChaCha12Rng::seed_from_u64(seed));
+        map
+    }
+
+    #[test]
+    fn leaves_a_wall_around_the_edge() {
+        for seed in 0..20 {
+            assert!(edge_is_wall(&bsp_map(seed)));
+        }
+    }
+
+    #[test]
+    fn every_room_can_be_reached() {
+        for seed in 0..20 {
+            let map = bsp_map(seed);
+
+            assert!(map.rooms.len() > 1);
+            assert_eq!(floor_area_count(&map), 1);
+        }
+    }
+
+    #[test]
+    fn rooms_never_overlap() {
+        for seed in 0..20 {
+            let map = bsp_map(seed);
+
+            for (i, room) in map.rooms.iter().enumerate() {
+                assert!(map.rooms[..i].iter().all(|other| !other.intersect(room)));
+            }
+        }
+    }
+}
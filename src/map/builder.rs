@@ -0,0 +1,79 @@
+use rand_chacha::ChaCha12Rng;
+
+use super::Map;
+
+/// One step in making a map. A base generator digs the layout into the solid map it is given,
+/// then passes after it tidy, join or decorate what is there, so new maps can be put together
+/// from the same pieces.
+pub trait MapBuilder {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng);
+}
+
+/// A list of builders run one after another over a map that starts out as solid wall.
+#[derive(Default)]
+pub struct MapPipeline {
+    builders: Vec<Box<dyn MapBuilder>>,
+}
+
+impl MapPipeline {
+    pub fn new() -> MapPipeline {
+        MapPipeline::default()
+    }
+
+    pub fn with(mut self, builder: impl MapBuilder + 'static) -> MapPipeline {
+        self.builders.push(Box::new(builder));
+        self
+    }
+
+    pub fn build(&self, width: i32, height: i32, rng: &mut ChaCha12Rng) -> Map {
+        let mut map = Map::new(width, height);
+
+        for builder in self.builders.iter() {
+            builder.build(&mut map, rng);
+        }
+
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::{passes::CullRooms, rooms::RandomRoomsBuilder, TileType};
+
+    fn build(pipeline: &MapPipeline) -> Map {
+        pipeline.build(60, 40, &mut ChaCha12Rng::seed_from_u64(0))
+    }
+
+    #[test]
+    fn an_empty_pipeline_leaves_solid_wall() {
+        let map = build(&MapPipeline::new());
+
+        assert_eq!(map.tiles.len(), 60 * 40);
+        assert!(map.tiles.iter().all(|tile| *tile == TileType::Wall));
+        assert!(map.rooms.is_empty());
+    }
+
+    #[test]
+    fn builders_run_in_the_order_they_were_added() {
+        let rooms = RandomRoomsBuilder {
+            max_rooms: 20,
+            min_size: 6,
+            max_size: 10,
+        };
+
+        // Culling before any rooms are dug has nothing to cull
+        let culled_first = build(&MapPipeline::new().with(CullRooms { keep: 1 }).with(rooms));
+        assert!(culled_first.rooms.len() > 1);
+
+        let rooms = RandomRoomsBuilder {
+            max_rooms: 20,
+            min_size: 6,
+            max_size: 10,
+        };
+        let culled_last = build(&MapPipeline::new().with(rooms).with(CullRooms { keep: 1 }));
+        assert_eq!(culled_last.rooms.len(), 1);
+    }
+}
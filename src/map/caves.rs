@@ -0,0 +1,90 @@
+use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+
+use super::{builder::MapBuilder, Map, TileType};
+
+const CLEAN_UP_ITERATIONS: u32 = 2;
+
+/// Cellular automata caves, following http://bfnightly.bracketproductions.com/rustbook/chapter_27.html
+/// The map starts as random noise which is smoothed into caverns. The caverns aren't all joined
+/// up, so this is followed by `KeepLargestArea`, and having no rooms, by `FindRegions`.
+pub struct CavesBuilder {
+    // Chance out of 100 of each tile starting as a wall before the caves are smoothed
+    pub wall_percent: i32,
+    pub iterations: u32,
+}
+
+impl MapBuilder for CavesBuilder {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng) {
+        for y in 1..map.height - 1 {
+            for x in 1..map.width - 1 {
+                if rng.gen_range(0..100) >= self.wall_percent {
+                    let idx = map.xy_idx(x, y);
+                    map.tiles[idx] = TileType::Floor;
+                }
+            }
+        }
+
+        for _ in 0..self.iterations {
+            map.smooth_caves(true);
+        }
+        // Tidies up the specks of wall left by the pillars
+        for _ in 0..CLEAN_UP_ITERATIONS {
+            map.smooth_caves(false);
+        }
+    }
+}
+
+impl Map {
+    // Tiles surrounded by walls become walls and tiles out in the open become floor. With pillars,
+    // floor tiles with no walls around them at all become walls to break up big open spaces.
+    fn smooth_caves(&mut self, pillars: bool) {
+        let mut new_tiles = self.tiles.clone();
+
+        for y in 1..self.height - 1 {
+            for x in 1..self.width - 1 {
+                let mut neighbouring_walls = 0;
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        if (dx != 0 || dy != 0)
+                            && self.tiles[self.xy_idx(x + dx, y + dy)] == TileType::Wall
+                        {
+                            neighbouring_walls += 1;
+                        }
+                    }
+                }
+
+                let idx = self.xy_idx(x, y);
+                new_tiles[idx] = if neighbouring_walls > 4 || (pillars && neighbouring_walls == 0) {
+                    TileType::Wall
+                } else {
+                    TileType::Floor
+                };
+            }
+        }
+
+        self.tiles = new_tiles;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::checks::edge_is_wall;
+
+    #[test]
+    fn leaves_a_wall_around_the_edge() {
+        for seed in 0..20 {
+            let mut map = Map::new(60, 40);
+            CavesBuilder {
+                wall_percent: 55,
+                iterations: 4,
+            }
+            .build(&mut map, &mut ChaCha12Rng::seed_from_u64(seed));
+
+            assert!(edge_is_wall(&map));
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::collections::VecDeque;
+
+use super::{Map, TileType};
+
+// Whether every tile around the edge of the map is wall
+pub fn edge_is_wall(map: &Map) -> bool {
+    (0..map.height).all(|y| {
+        (0..map.width).all(|x| {
+            let on_edge = x == 0 || y == 0 || x == map.width - 1 || y == map.height - 1;
+            !on_edge || map.tiles[map.xy_idx(x, y)] == TileType::Wall
+        })
+    })
+}
+
+// How many separate areas of floor the map has, counting only steps up, down, left and right
+pub fn floor_area_count(map: &Map) -> usize {
+    let mut seen = vec![false; map.tiles.len()];
+    let mut areas = 0;
+
+    for start in 0..map.tiles.len() {
+        if seen[start] || map.tiles[start] != TileType::Floor {
+            continue;
+        }
+
+        areas += 1;
+        seen[start] = true;
+        let mut open = VecDeque::from(vec![start]);
+        while let Some(idx) = open.pop_front() {
+            let (x, y) = (idx as i32 % map.width, idx as i32 / map.width);
+            for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)].iter() {
+                if *nx < 0 || *ny < 0 || *nx >= map.width || *ny >= map.height {
+                    continue;
+                }
+                let next = map.xy_idx(*nx, *ny);
+                if !seen[next] && map.tiles[next] == TileType::Floor {
+                    seen[next] = true;
+                    open.push_back(next);
+                }
+            }
+        }
+    }
+
+    areas
+}
+
+// The share of the tiles inside the outer wall that are floor, as a percentage
+pub fn floor_percent(map: &Map) -> i32 {
+    let floor = map
+        .tiles
+        .iter()
+        .filter(|tile| **tile == TileType::Floor)
+        .count() as i32;
+    floor * 100 / ((map.width - 2) * (map.height - 2))
+}
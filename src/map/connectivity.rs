@@ -0,0 +1,218 @@
+use std::collections::VecDeque;
+
+use super::{Map, TileType};
+use crate::position::Position;
+
+/// What it took to get a map every creature can get around.
+#[derive(Default)]
+pub struct MapReport {
+    // How many maps were made, counting the one kept
+    pub attempts: u32,
+    // Areas of floor that couldn't be reached until a corridor was dug to them
+    pub areas_joined: u32,
+}
+
+impl MapReport {
+    // Lines for the game log, with nothing to say about a map that came out fine first time
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        if self.attempts > 1 {
+            lines.push(format!(
+                "The map was made {} times before creatures had room to start in and rooms to wander to",
+                self.attempts
+            ));
+        }
+        if self.areas_joined > 0 {
+            lines.push(format!(
+                "{} unreachable areas of the map were joined on with extra corridors",
+                self.areas_joined
+            ));
+        }
+
+        lines
+    }
+}
+
+impl Map {
+    // Digs a corridor from each area of floor that can't be reached to the nearest other area,
+    // through wall only, so everything else on the map is left as it is. Returns how many areas
+    // were joined on, or None when an area is shut in by something that can't be dug through.
+    // Corridors are always dug the same way, so no random numbers are used.
+    pub(super) fn join_unreachable_areas(&mut self) -> Option<u32> {
+        let mut joined = 0;
+
+        loop {
+            let (area_of, area_sizes) = self.floor_areas();
+            if area_sizes.len() < 2 {
+                return Some(joined);
+            }
+
+            let biggest = (0..area_sizes.len())
+                .max_by_key(|area| area_sizes[*area])
+                .unwrap();
+            // The first area found that isn't the biggest, which is the one nearest the top left
+            let stray = if biggest == 0 { 1 } else { 0 };
+
+            for idx in self.corridor_from(stray, &area_of)? {
+                self.tiles[idx] = TileType::Floor;
+            }
+            joined += 1;
+        }
+    }
+
+    // The shortest run of wall between an area and any other, found by searching outwards from
+    // every tile of the area at once. The edge of the map is never dug through.
+    fn corridor_from(&self, area: usize, area_of: &[Option<usize>]) -> Option<Vec<usize>> {
+        let mut came_from: Vec<Option<usize>> = vec![None; self.tiles.len()];
+        let mut open = VecDeque::new();
+
+        for (idx, tile_area) in area_of.iter().enumerate() {
+            if *tile_area == Some(area) {
+                came_from[idx] = Some(idx);
+                open.push_back(idx);
+            }
+        }
+
+        while let Some(idx) = open.pop_front() {
+            let x = idx as i32 % self.width;
+            let y = idx as i32 / self.width;
+            for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)].iter() {
+                if !self.is_inside_edge(*nx, *ny) {
+                    continue;
+                }
+                let neighbour = self.xy_idx(*nx, *ny);
+                if came_from[neighbour].is_some() {
+                    continue;
+                }
+                came_from[neighbour] = Some(idx);
+
+                // Another area, so dig back along the way the search came
+                if area_of[neighbour].is_some() {
+                    let mut corridor = Vec::new();
+                    let mut step = idx;
+                    while area_of[step] != Some(area) {
+                        corridor.push(step);
+                        step = came_from[step].unwrap();
+                    }
+                    return Some(corridor);
+                }
+
+                if self.tiles[neighbour] == TileType::Wall {
+                    open.push_back(neighbour);
+                }
+            }
+        }
+
+        None
+    }
+
+    // Creatures wander to the centres of rooms, so a map is only any use if it has rooms and
+    // every centre is floor
+    pub(super) fn has_destinations(&self) -> bool {
+        !self.rooms.is_empty()
+            && self.rooms.iter().all(|room| {
+                let Position(x, y) = room.center();
+                self.tiles[self.xy_idx(x, y)] == TileType::Floor
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha12Rng;
+
+    use super::*;
+    use crate::{
+        map::{
+            builder::MapBuilder,
+            passes::{CorridorStyle, JoinRooms},
+            rooms::RandomRoomsBuilder,
+        },
+        rect::Rect,
+    };
+
+    // Rooms that nothing has joined up yet
+    fn rooms_map(seed: u64) -> Map {
+        let mut map = Map::new(60, 40);
+        RandomRoomsBuilder {
+            max_rooms: 20,
+            min_size: 6,
+            max_size: 10,
+        }
+        .build(&mut map, &mut ChaCha12Rng::seed_from_u64(seed));
+        map
+    }
+
+    fn floor_tiles(map: &Map) -> usize {
+        map.tiles
+            .iter()
+            .filter(|tile| **tile == TileType::Floor)
+            .count()
+    }
+
+    #[test]
+    fn unreachable_areas_are_joined_on() {
+        for seed in 0..10 {
+            let mut map = rooms_map(seed);
+            let (_, area_sizes) = map.floor_areas();
+            let areas = area_sizes.len();
+            let floor = floor_tiles(&map);
+
+            // A corridor can pass through other stray areas on its way, joining them on too
+            let joined = map.join_unreachable_areas().unwrap();
+
+            assert!(joined > 0 && (joined as usize) < areas);
+            let (_, area_sizes) = map.floor_areas();
+            assert_eq!(area_sizes.len(), 1);
+            assert!(floor_tiles(&map) > floor);
+        }
+    }
+
+    #[test]
+    fn maps_that_are_already_joined_are_left_alone() {
+        let mut map = rooms_map(4);
+        JoinRooms {
+            style: CorridorStyle::Sequential,
+        }
+        .build(&mut map, &mut ChaCha12Rng::seed_from_u64(4));
+        let tiles = map.tiles.clone();
+
+        assert_eq!(map.join_unreachable_areas(), Some(0));
+        assert_eq!(map.tiles, tiles);
+    }
+
+    #[test]
+    fn areas_cut_off_by_chasm_cant_be_joined() {
+        let mut map = Map::new(9, 5);
+        for x in 1..=7 {
+            for y in 1..=3 {
+                let idx = map.xy_idx(x, y);
+                map.tiles[idx] = match x {
+                    3 => TileType::Wall,
+                    4 => TileType::Chasm,
+                    _ => TileType::Floor,
+                };
+            }
+        }
+
+        assert_eq!(map.join_unreachable_areas(), None);
+    }
+
+    #[test]
+    fn destinations_need_rooms_with_floor_in_the_centre() {
+        let mut map = rooms_map(0);
+        assert!(map.has_destinations());
+
+        let Position(x, y) = map.rooms[0].center();
+        let idx = map.xy_idx(x, y);
+        map.tiles[idx] = TileType::Wall;
+        assert!(!map.has_destinations());
+
+        let mut solid = Map::new(20, 20);
+        assert!(!solid.has_destinations());
+        solid.rooms.push(Rect::new(5, 5, 4, 4));
+        assert!(!solid.has_destinations());
+    }
+}
@@ -0,0 +1,110 @@
+use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+
+use super::{builder::MapBuilder, Map, TileType};
+
+/// Diffusion limited aggregation, following http://bfnightly.bracketproductions.com/rustbook/chapter_30.html
+/// A small cross of floor is dug in the middle, then particles wander in from random spots on the
+/// map. When a particle bumps into floor the tile it came from is dug out, so the floor grows
+/// outwards in connected branches until floor_percent of the map is floor.
+pub struct DlaBuilder {
+    pub floor_percent: i32,
+}
+
+impl MapBuilder for DlaBuilder {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng) {
+        let (width, height) = (map.width, map.height);
+
+        let (centre_x, centre_y) = (width / 2, height / 2);
+        for (x, y) in [
+            (centre_x, centre_y),
+            (centre_x - 1, centre_y),
+            (centre_x + 1, centre_y),
+            (centre_x, centre_y - 1),
+            (centre_x, centre_y + 1),
+        ]
+        .iter()
+        {
+            let idx = map.xy_idx(*x, *y);
+            map.tiles[idx] = TileType::Floor;
+        }
+
+        // The outer wall is never dug, so the share of floor only counts the tiles inside it
+        let target_floor = ((width - 2) * (height - 2) * self.floor_percent / 100) as usize;
+        let mut floor = map
+            .tiles
+            .iter()
+            .filter(|tile| **tile == TileType::Floor)
+            .count();
+
+        while floor < target_floor {
+            let mut x = rng.gen_range(1..width - 1);
+            let mut y = rng.gen_range(1..height - 1);
+            let (mut prev_x, mut prev_y) = (x, y);
+
+            while map.tiles[map.xy_idx(x, y)] == TileType::Wall {
+                prev_x = x;
+                prev_y = y;
+
+                match rng.gen_range(0..4) {
+                    0 => x -= 1,
+                    1 => x += 1,
+                    2 => y -= 1,
+                    _ => y += 1,
+                }
+
+                // Stay off the edge of the map so it keeps its outer wall
+                x = x.clamp(1, width - 2);
+                y = y.clamp(1, height - 2);
+            }
+
+            // A particle that started on floor has nowhere new to dig
+            let idx = map.xy_idx(prev_x, prev_y);
+            if map.tiles[idx] == TileType::Wall {
+                map.tiles[idx] = TileType::Floor;
+                floor += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::checks::{edge_is_wall, floor_area_count, floor_percent};
+
+    const FLOOR_PERCENT: i32 = 25;
+
+    // 60 by 40 tiles inside the outer wall, so the share of floor comes out exactly
+    fn dla_map(seed: u64) -> Map {
+        let mut map = Map::new(62, 42);
+        DlaBuilder {
+            floor_percent: FLOOR_PERCENT,
+        }
+        .build(&mut map, &mut ChaCha12Rng::seed_from_u64(seed));
+        map
+    }
+
+    #[test]
+    fn leaves_a_wall_around_the_edge() {
+        for seed in 0..5 {
+            assert!(edge_is_wall(&dla_map(seed)));
+        }
+    }
+
+    #[test]
+    fn all_the_floor_is_joined_up() {
+        for seed in 0..5 {
+            assert_eq!(floor_area_count(&dla_map(seed)), 1);
+        }
+    }
+
+    #[test]
+    fn digs_until_floor_percent_is_reached() {
+        for seed in 0..5 {
+            assert!(floor_percent(&dla_map(seed)) >= FLOOR_PERCENT);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+
+use super::{builder::MapBuilder, Map, TileType};
+
+/// Drunkard's walk, following http://bfnightly.bracketproductions.com/rustbook/chapter_28.html
+/// Diggers stagger about at random, each starting from floor an earlier one dug so every tile they
+/// dig is connected, until floor_percent of the map is floor.
+pub struct DrunkardsWalkBuilder {
+    pub floor_percent: i32,
+    // How many steps each digger takes
+    pub lifetime: i32,
+}
+
+impl MapBuilder for DrunkardsWalkBuilder {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng) {
+        let (width, height) = (map.width, map.height);
+
+        // The outer wall is never dug, so the share of floor only counts the tiles inside it
+        let target_floor = ((width - 2) * (height - 2) * self.floor_percent / 100) as usize;
+        let start = map.xy_idx(width / 2, height / 2);
+        map.tiles[start] = TileType::Floor;
+        let mut floor = vec![start];
+
+        while floor.len() < target_floor {
+            let start = floor[rng.gen_range(0..floor.len())];
+            let mut x = start as i32 % width;
+            let mut y = start as i32 / width;
+
+            for _ in 0..self.lifetime {
+                match rng.gen_range(0..4) {
+                    0 => x -= 1,
+                    1 => x += 1,
+                    2 => y -= 1,
+                    _ => y += 1,
+                }
+
+                // Stay off the edge of the map so it keeps its outer wall
+                x = x.clamp(1, width - 2);
+                y = y.clamp(1, height - 2);
+
+                let idx = map.xy_idx(x, y);
+                if map.tiles[idx] == TileType::Wall {
+                    map.tiles[idx] = TileType::Floor;
+                    floor.push(idx);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::checks::{edge_is_wall, floor_area_count, floor_percent};
+
+    const FLOOR_PERCENT: i32 = 40;
+
+    // 60 by 40 tiles inside the outer wall, so the share of floor comes out exactly
+    fn drunkards_walk_map(seed: u64) -> Map {
+        let mut map = Map::new(62, 42);
+        DrunkardsWalkBuilder {
+            floor_percent: FLOOR_PERCENT,
+            lifetime: 400,
+        }
+        .build(&mut map, &mut ChaCha12Rng::seed_from_u64(seed));
+        map
+    }
+
+    #[test]
+    fn leaves_a_wall_around_the_edge() {
+        for seed in 0..5 {
+            assert!(edge_is_wall(&drunkards_walk_map(seed)));
+        }
+    }
+
+    #[test]
+    fn all_the_floor_is_joined_up() {
+        for seed in 0..5 {
+            assert_eq!(floor_area_count(&drunkards_walk_map(seed)), 1);
+        }
+    }
+
+    #[test]
+    fn digs_until_floor_percent_is_reached() {
+        for seed in 0..5 {
+            assert!(floor_percent(&drunkards_walk_map(seed)) >= FLOOR_PERCENT);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::fmt;
+
+use rand::prelude::SliceRandom;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+use super::{Map, MapReport, MapSettings, TileType};
+use crate::position::Position;
+
+/// The level of the dungeon an entity is on, counting down from 0 at the top.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Level(pub usize);
+
+// Levels are numbered from 1 wherever people read them
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0 + 1)
+    }
+}
+
+/// Every level of the dungeon, from the top down. Each level has stairs down to the one below
+/// it, which come out on the stairs up from that level.
+pub struct Dungeon {
+    pub levels: Vec<Map>,
+}
+
+impl Dungeon {
+    /// Makes every level with the same settings, then puts stairs between each level and the one
+    /// below it. The top level is made first, so a dungeon with one level is the same map the
+    /// seed has always made. `creatures` is how many creatures start on each level.
+    pub fn generate(
+        settings: &MapSettings,
+        creatures: &[usize],
+        rng: &mut ChaCha12Rng,
+    ) -> Result<(Dungeon, Vec<MapReport>), String> {
+        let (mut levels, reports): (Vec<Map>, Vec<MapReport>) = (0..settings.levels)
+            .map(|level| {
+                Map::generate(
+                    settings,
+                    creatures.get(level as usize).copied().unwrap_or(0),
+                    rng,
+                )
+            })
+            .collect::<Result<Vec<(Map, MapReport)>, String>>()?
+            .into_iter()
+            .unzip();
+
+        for below in 1..levels.len() {
+            levels[below - 1].place_stairs(TileType::DownStairs, rng);
+            levels[below].place_stairs(TileType::UpStairs, rng);
+        }
+
+        Ok((Dungeon { levels }, reports))
+    }
+
+    pub fn map(&self, level: &Level) -> &Map {
+        &self.levels[level.0]
+    }
+
+    pub fn map_mut(&mut self, level: &Level) -> &mut Map {
+        &mut self.levels[level.0]
+    }
+
+    // Every level is the same size
+    pub fn width(&self) -> i32 {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.levels[0].height
+    }
+
+    /// The level and spot the stairs at `position` lead to, if there are stairs there and a level
+    /// for them to lead to.
+    pub fn stairs_exit(&self, level: &Level, position: &Position) -> Option<(Level, Position)> {
+        let map = self.map(level);
+
+        let (to, arrival) = match map.tiles[map.xy_idx(position.0, position.1)] {
+            TileType::DownStairs => (level.0 + 1, TileType::UpStairs),
+            TileType::UpStairs if level.0 > 0 => (level.0 - 1, TileType::DownStairs),
+            _ => return None,
+        };
+
+        let spot = self.levels.get(to)?.find_tile(arrival)?;
+        Some((Level(to), spot))
+    }
+
+    /// Every set of stairs on the level that leads somewhere.
+    pub fn stairs(&self, level: &Level) -> Vec<Position> {
+        let map = self.map(level);
+
+        (0..map.tiles.len() as i32)
+            .map(|idx| Position(idx % map.width, idx / map.width))
+            .filter(|spot| self.stairs_exit(level, spot).is_some())
+            .collect()
+    }
+}
+
+// Stairs come out on the one set of stairs going the other way, so a level, or anything stamped
+// into one, can only have one set going each way
+pub(super) fn check_stairs(tiles: impl Iterator<Item = TileType>) -> Result<(), String> {
+    let (mut down, mut up) = (0, 0);
+    for tile in tiles {
+        match tile {
+            TileType::DownStairs => down += 1,
+            TileType::UpStairs => up += 1,
+            _ => {}
+        }
+    }
+
+    for (glyph, count) in [('>', down), ('<', up)].iter() {
+        if *count > 1 {
+            return Err(format!(
+                "{} '{}' tiles, but stairs can only lead to one",
+                count, glyph
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+impl Map {
+    // The first tile of this type, reading from the top left
+    fn find_tile(&self, tile: TileType) -> Option<Position> {
+        let idx = self.tiles.iter().position(|map_tile| *map_tile == tile)? as i32;
+        Some(Position(idx % self.width, idx / self.width))
+    }
+
+    // Stairs go on a random tile of floor, away from the room centres creatures wander to, the
+    // spots prefabs spawn things on and the zones creatures start in. Maps that already have the
+    // stairs, from a map file or a prefab, are left as they are.
+    fn place_stairs(&mut self, stairs: TileType, rng: &mut ChaCha12Rng) {
+        if self.tiles.contains(&stairs) {
+            return;
+        }
+
+        let protected: Vec<usize> = self
+            .rooms
+            .iter()
+            .map(|room| room.center())
+            .chain(self.prefab_spawns.iter().map(|(spot, _)| spot.clone()))
+            .map(|Position(x, y)| self.xy_idx(x, y))
+            .collect();
+        // Zones count their floor from one tile in from their top left corner
+        let in_spawn_zone = |x: i32, y: i32| {
+            self.spawn_zones
+                .iter()
+                .any(|zone| x > zone.x1 && x <= zone.x2 && y > zone.y1 && y <= zone.y2)
+        };
+
+        let spots: Vec<usize> = (0..self.tiles.len())
+            .filter(|idx| self.tiles[*idx] == TileType::Floor && !protected.contains(idx))
+            .filter(|idx| !in_spawn_zone(*idx as i32 % self.width, *idx as i32 / self.width))
+            .collect();
+
+        if let Some(idx) = spots.choose(rng) {
+            self.tiles[*idx] = stairs;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::MapGenerator;
+
+    fn settings(levels: u32) -> MapSettings {
+        MapSettings {
+            width: 60,
+            height: 40,
+            generator: MapGenerator::RoomsAndCorridors {
+                max_rooms: 20,
+                min_size: 6,
+                max_size: 10,
+            },
+            passes: Vec::new(),
+            levels,
+        }
+    }
+
+    fn count(map: &Map, stairs: TileType) -> usize {
+        map.tiles.iter().filter(|tile| **tile == stairs).count()
+    }
+
+    #[test]
+    fn each_level_has_one_pair_of_stairs_to_the_next() {
+        for seed in 0..5 {
+            let mut rng = ChaCha12Rng::seed_from_u64(seed);
+            let (dungeon, reports) = Dungeon::generate(&settings(3), &[], &mut rng).unwrap();
+            assert_eq!(dungeon.levels.len(), 3);
+            assert_eq!(reports.len(), 3);
+
+            for (level, map) in dungeon.levels.iter().enumerate() {
+                let down = if level < 2 { 1 } else { 0 };
+                let up = if level > 0 { 1 } else { 0 };
+                assert_eq!(count(map, TileType::DownStairs), down, "seed {}", seed);
+                assert_eq!(count(map, TileType::UpStairs), up, "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn stairs_come_out_on_the_stairs_going_back() {
+        let mut rng = ChaCha12Rng::seed_from_u64(0);
+        let (dungeon, _) = Dungeon::generate(&settings(2), &[], &mut rng).unwrap();
+
+        let down = dungeon
+            .map(&Level(0))
+            .find_tile(TileType::DownStairs)
+            .unwrap();
+        let up = dungeon
+            .map(&Level(1))
+            .find_tile(TileType::UpStairs)
+            .unwrap();
+        assert_eq!(
+            dungeon.stairs_exit(&Level(0), &down),
+            Some((Level(1), up.clone()))
+        );
+        assert_eq!(dungeon.stairs_exit(&Level(1), &up), Some((Level(0), down)));
+        assert_eq!(dungeon.stairs(&Level(1)), vec![up]);
+    }
+
+    #[test]
+    fn one_level_has_no_stairs() {
+        let mut rng = ChaCha12Rng::seed_from_u64(0);
+        let (dungeon, _) = Dungeon::generate(&settings(1), &[], &mut rng).unwrap();
+
+        assert_eq!(count(&dungeon.levels[0], TileType::DownStairs), 0);
+        assert!(dungeon.stairs(&Level(0)).is_empty());
+    }
+
+    #[test]
+    fn stairs_can_only_go_each_way_once() {
+        let tiles = |tiles: &[TileType]| check_stairs(tiles.iter().copied());
+
+        assert!(tiles(&[TileType::DownStairs, TileType::UpStairs]).is_ok());
+        assert!(tiles(&[TileType::DownStairs, TileType::DownStairs]).is_err());
+        assert!(tiles(&[TileType::UpStairs, TileType::Floor, TileType::UpStairs]).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use rand_chacha::ChaCha12Rng;
+
+use super::{
+    builder::MapBuilder, char_to_tile, dungeon::check_stairs, Map, TileType, ORGANIC_MAP_REGIONS,
+};
+use crate::rect::Rect;
+
+// A floor tile creatures start on
+const SPAWN_MARKER: char = 'S';
+
+pub fn read_map_file(path: &Path) -> Result<Vec<String>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+
+    Ok(contents.lines().map(|line| line.to_string()).collect())
+}
+
+/// Makes a map from the rows of a map file. Every tile, stairs included, uses the same glyph the
+/// map is drawn with. `S` is floor that creatures start on, and digits are floor marking out
+/// rooms: the smallest rectangle around every tile with the same digit is a room, so marking two
+/// opposite corners is enough. Without any rooms marked, open rectangles of floor are picked out as rooms.
+/// The edge of the map has to be wall and all the floor has to be joined up.
+pub fn parse_map_rows(rows: &[String]) -> Result<Map, String> {
+    let height = rows.len() as i32;
+    let width = rows.first().map_or(0, |row| row.chars().count()) as i32;
+    if width < 3 || height < 3 {
+        return Err(format!(
+            "A {}x{} map is too small to hold any floor",
+            width, height
+        ));
+    }
+
+    let mut map = Map::new(width, height);
+    let mut room_tiles: BTreeMap<char, Vec<(i32, i32)>> = BTreeMap::new();
+
+    for (y, row) in rows.iter().enumerate() {
+        let y = y as i32;
+        if row.chars().count() as i32 != width {
+            return Err(format!("Map row {} is not {} tiles wide", y, width));
+        }
+
+        for (x, glyph) in row.chars().enumerate() {
+            let x = x as i32;
+            let tile = match glyph {
+                SPAWN_MARKER => {
+                    map.spawn_zones.push(Rect::new(x - 1, y - 1, 1, 1));
+                    TileType::Floor
+                }
+                '0'..='9' => {
+                    room_tiles.entry(glyph).or_default().push((x, y));
+                    TileType::Floor
+                }
+                _ => match char_to_tile(glyph) {
+                    Some(tile) => tile,
+                    None => return Err(format!("Unknown map tile '{}' at {},{}", glyph, x, y)),
+                },
+            };
+
+            let on_edge = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+            if on_edge && tile != TileType::Wall {
+                return Err(format!("The edge of the map at {},{} must be wall", x, y));
+            }
+
+            let idx = map.xy_idx(x, y);
+            map.tiles[idx] = tile;
+        }
+    }
+
+    check_stairs(map.tiles.iter().copied()).map_err(|error| format!("The map has {}", error))?;
+
+    for (digit, tiles) in room_tiles.iter() {
+        let x1 = tiles.iter().map(|(x, _)| *x).min().unwrap();
+        let x2 = tiles.iter().map(|(x, _)| *x).max().unwrap();
+        let y1 = tiles.iter().map(|(_, y)| *y).min().unwrap();
+        let y2 = tiles.iter().map(|(_, y)| *y).max().unwrap();
+
+        // Creatures spawn anywhere in a room and wander to its centre
+        for y in y1..=y2 {
+            for x in x1..=x2 {
+                if map.tiles[map.xy_idx(x, y)] != TileType::Floor {
+                    return Err(format!(
+                        "Room {} has a wall or door in it at {},{}",
+                        digit, x, y
+                    ));
+                }
+            }
+        }
+
+        // Rooms count their floor from one tile in from their top left corner
+        map.rooms
+            .push(Rect::new(x1 - 1, y1 - 1, x2 - x1 + 1, y2 - y1 + 1));
+    }
+
+    let (_, area_sizes) = map.floor_areas();
+    match area_sizes.len() {
+        0 => return Err("The map has no floor".to_string()),
+        1 => {}
+        areas => {
+            return Err(format!(
+                "The floor is split into {} areas that can't reach each other",
+                areas
+            ))
+        }
+    }
+
+    if map.rooms.is_empty() {
+        map.rooms = map.find_regions(ORGANIC_MAP_REGIONS);
+    }
+    // Creatures always need somewhere to wander to
+    if map.rooms.is_empty() {
+        return Err("The map has no rooms marked and no open space to use as rooms".to_string());
+    }
+
+    Ok(map)
+}
+
+/// Replaces the map with one read from a map file, checked when the scenario was loaded.
+pub struct MapFileBuilder {
+    pub rows: Vec<String>,
+}
+
+impl MapBuilder for MapFileBuilder {
+    fn build(&self, map: &mut Map, _rng: &mut ChaCha12Rng) {
+        *map = parse_map_rows(&self.rows).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(rows: &[&str]) -> Result<Map, String> {
+        let rows: Vec<String> = rows.iter().map(|row| row.to_string()).collect();
+        parse_map_rows(&rows)
+    }
+
+    #[test]
+    fn the_arena_map_file_parses() {
+        let rows = read_map_file(Path::new("assets/maps/arena.txt")).unwrap();
+        assert!(parse_map_rows(&rows).is_ok());
+    }
+
+    #[test]
+    fn markers_become_rooms_and_spawn_zones() {
+        let map = parse(&[
+            "########", //
+            "#1..#..#", "#..1#S.#", "#......#", "########",
+        ])
+        .unwrap();
+
+        // Rooms and zones cover the floor from one tile in from their top left corner
+        let floor = |rect: &Rect| (rect.x1 + 1, rect.y1 + 1, rect.x2, rect.y2);
+        assert_eq!(
+            map.rooms.iter().map(floor).collect::<Vec<_>>(),
+            [(1, 1, 3, 2)]
+        );
+        assert_eq!(
+            map.spawn_zones.iter().map(floor).collect::<Vec<_>>(),
+            [(5, 2, 5, 2)]
+        );
+    }
+
+    #[test]
+    fn open_space_is_used_when_no_rooms_are_marked() {
+        let map = parse(&["######", "#....#", "#....#", "#....#", "######"]).unwrap();
+        assert!(!map.rooms.is_empty());
+    }
+
+    #[test]
+    fn bad_map_files_are_rejected() {
+        let bad_maps: &[&[&str]] = &[
+            // Too small
+            &["##", "##"],
+            // Ragged
+            &["#####", "#..#", "#####"],
+            // Unknown tile
+            &["#####", "#.?.#", "#####"],
+            // Floor on the edge
+            &["#####", "#....", "#####"],
+            // A wall inside a room
+            &["######", "#1#..#", "#..1.#", "######"],
+            // Floor that can't reach the rest
+            &["#######", "#..#..#", "#..#..#", "#######"],
+            // No floor at all
+            &["####", "####", "####"],
+            // Nowhere to wander to
+            &["#####", "#.#.#", "#...#", "#####"],
+            // Two sets of stairs down
+            &["######", "#>..>#", "#....#", "#....#", "######"],
+        ];
+
+        for rows in bad_maps.iter() {
+            assert!(parse(rows).is_err(), "{:?}", rows);
+        }
+    }
+}
@@ -0,0 +1,931 @@
+mod bsp;
+mod builder;
+mod caves;
+#[cfg(test)]
+mod checks;
+mod connectivity;
+mod dla;
+mod drunkard;
+mod dungeon;
+mod file;
+mod passes;
+mod prefab;
+mod regions;
+mod rooms;
+
+use self::{
+    bsp::BspBuilder,
+    builder::{MapBuilder, MapPipeline},
+    caves::CavesBuilder,
+    dla::DlaBuilder,
+    drunkard::DrunkardsWalkBuilder,
+    file::{parse_map_rows, read_map_file, MapFileBuilder},
+    passes::{CorridorStyle, CullRooms, Doors, JoinRooms, Pillars, SpawnZones, TerrainPools},
+    prefab::{load_prefab, StampPrefabs},
+    regions::{FindRegions, KeepLargestArea},
+    rooms::RandomRoomsBuilder,
+};
+use crate::{flow::FlowFields, position::Position, rect::Rect, render::ShownLevel};
+
+pub use self::{
+    connectivity::MapReport,
+    dungeon::{Dungeon, Level},
+    prefab::{Prefab, PrefabSpawn},
+};
+
+use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+use rltk::{Algorithm2D, BaseMap, Point};
+use std::{
+    cmp::{max, min},
+    path::Path,
+    str::FromStr,
+};
+
+use std::io::stdout;
+
+use bevy::prelude::{Entity, Res};
+use crossterm::{cursor, style, QueueableCommand};
+use serde::{Deserialize, Serialize};
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum TileType {
+    Wall,
+    Floor,
+    // Closed doors block sight but creatures can open them and walk through
+    Door { open: bool },
+    // Slow to wade through
+    ShallowWater,
+    // Slow to clamber over
+    Rubble,
+    // Burns anyone standing in it
+    Lava,
+    // Can be seen across but not crossed
+    Chasm,
+    // Lead to the level below and the level above
+    DownStairs,
+    UpStairs,
+}
+
+// Extra cost of a path through lava, so creatures only cross it when there's no way round
+const LAVA_PATH_PENALTY: i32 = 10;
+
+impl TileType {
+    pub fn is_walkable(&self) -> bool {
+        !matches!(self, TileType::Wall | TileType::Chasm)
+    }
+
+    // How many ticks it takes to move off this tile after stepping onto it
+    pub fn move_time(&self) -> i32 {
+        match self {
+            TileType::ShallowWater | TileType::Rubble => 2,
+            _ => 1,
+        }
+    }
+
+    // What a step onto this tile costs when working out a path, so creatures go round slow and
+    // dangerous ground when they can
+    pub fn path_cost(&self) -> i32 {
+        match self {
+            TileType::Lava => self.move_time() + LAVA_PATH_PENALTY,
+            _ => self.move_time(),
+        }
+    }
+
+    // Creatures can start on ground that only slows them down, but not in a doorway, on the stairs
+    // or anywhere that hurts them
+    pub fn can_start_on(&self) -> bool {
+        matches!(
+            self,
+            TileType::Floor | TileType::ShallowWater | TileType::Rubble
+        )
+    }
+
+    // Tiles the terrain pass can scatter over the floor
+    pub fn is_terrain(&self) -> bool {
+        matches!(
+            self,
+            TileType::ShallowWater | TileType::Rubble | TileType::Lava | TileType::Chasm
+        )
+    }
+}
+
+// Which algorithm lays out the map, and its parameters
+#[derive(Clone, Deserialize)]
+pub enum MapGenerator {
+    RoomsAndCorridors {
+        max_rooms: i32,
+        min_size: i32,
+        max_size: i32,
+    },
+    // Rooms like RoomsAndCorridors with nothing joining them, for scenarios that dig their own
+    // corridors with a JoinRooms pass
+    Rooms {
+        max_rooms: i32,
+        min_size: i32,
+        max_size: i32,
+    },
+    // Binary space partition: the map is split in two again and again, with a room in each piece
+    Bsp {
+        // Pieces are never split smaller than this, in tiles across
+        min_leaf_size: i32,
+        min_room_size: i32,
+    },
+    // Cellular automata caves, with open rectangles of floor picked out as rooms
+    Caves {
+        // Chance out of 100 of each tile starting as a wall before the caves are smoothed
+        wall_percent: i32,
+        iterations: u32,
+        max_regions: u32,
+    },
+    // Random diggers wandering out from the middle of the map
+    DrunkardsWalk {
+        // How much of the map is dug out before the diggers stop
+        floor_percent: i32,
+        // How many steps each digger takes
+        lifetime: i32,
+    },
+    // Diffusion limited aggregation: particles wander in and stick to the floor dug so far
+    Dla {
+        floor_percent: i32,
+    },
+    // A fixed map read from an ASCII file, the same every game
+    File {
+        path: String,
+        // Read from the file when the scenario is loaded
+        #[serde(skip)]
+        rows: Vec<String>,
+    },
+}
+
+// Settings that keep making maps without rooms are given up on after this many tries
+const MAX_MAP_ATTEMPTS: u32 = 10;
+
+// The stairs put in once every level is made can each take a tile creatures would start on,
+// unless the map has spawn zones for them to keep out of
+const STAIRS_PER_LEVEL: usize = 2;
+
+// Maps from the winding generators have no rooms, so this many regions are picked out instead
+const ORGANIC_MAP_REGIONS: u32 = 30;
+
+// About the least floor the caves make with their default settings. Denser caves get fewer pools
+// of terrain, so the pools don't fill up what little floor there is.
+const CAVE_POOL_FLOOR: u32 = 1200;
+
+impl MapGenerator {
+    // The base generator and the passes that always go with it
+    fn pipeline(&self) -> MapPipeline {
+        match *self {
+            MapGenerator::RoomsAndCorridors {
+                max_rooms,
+                min_size,
+                max_size,
+            } => MapPipeline::new()
+                .with(RandomRoomsBuilder {
+                    max_rooms,
+                    min_size,
+                    max_size,
+                })
+                .with(JoinRooms {
+                    style: CorridorStyle::Sequential,
+                })
+                .with(Doors),
+            MapGenerator::Rooms {
+                max_rooms,
+                min_size,
+                max_size,
+            } => MapPipeline::new().with(RandomRoomsBuilder {
+                max_rooms,
+                min_size,
+                max_size,
+            }),
+            MapGenerator::Bsp {
+                min_leaf_size,
+                min_room_size,
+            } => MapPipeline::new()
+                .with(BspBuilder {
+                    min_leaf_size,
+                    min_room_size,
+                })
+                .with(Doors),
+            MapGenerator::Caves {
+                wall_percent,
+                iterations,
+                max_regions,
+            } => MapPipeline::new()
+                .with(CavesBuilder {
+                    wall_percent,
+                    iterations,
+                })
+                .with(KeepLargestArea)
+                .with(FindRegions { max_regions })
+                .with(TerrainPools {
+                    tile: TileType::ShallowWater,
+                    pools: 4,
+                    size: 30,
+                    full_floor: Some(CAVE_POOL_FLOOR),
+                })
+                .with(TerrainPools {
+                    tile: TileType::Rubble,
+                    pools: 8,
+                    size: 4,
+                    full_floor: Some(CAVE_POOL_FLOOR),
+                }),
+            MapGenerator::DrunkardsWalk {
+                floor_percent,
+                lifetime,
+            } => MapPipeline::new()
+                .with(DrunkardsWalkBuilder {
+                    floor_percent,
+                    lifetime,
+                })
+                .with(FindRegions {
+                    max_regions: ORGANIC_MAP_REGIONS,
+                }),
+            MapGenerator::Dla { floor_percent } => MapPipeline::new()
+                .with(DlaBuilder { floor_percent })
+                .with(FindRegions {
+                    max_regions: ORGANIC_MAP_REGIONS,
+                }),
+            MapGenerator::File { ref rows, .. } => {
+                MapPipeline::new().with(MapFileBuilder { rows: rows.clone() })
+            }
+        }
+    }
+}
+
+// Extra steps a scenario runs over the map after its generator, in the order they are listed
+#[derive(Clone, Deserialize)]
+pub enum MapPass {
+    // Fills in rooms at random until only this many are left
+    CullRooms {
+        keep: u32,
+    },
+    JoinRooms(CorridorStyle),
+    // Hangs closed doors where corridors open into rooms
+    Doors,
+    // Picks this many rooms far apart from each other for creatures to start in
+    SpawnZones {
+        count: u32,
+    },
+    // Chance out of 100 of putting a pillar on each open tile in the rooms
+    Pillars {
+        percent: i32,
+    },
+    // Scatters this many pools of terrain over the floor, each up to size tiles across
+    Terrain {
+        tile: TileType,
+        pools: u32,
+        size: u32,
+    },
+    // Stamps this many prefabs picked at random from the files into open floor
+    Prefabs {
+        files: Vec<String>,
+        count: u32,
+        // Read from the files when the scenario is loaded
+        #[serde(skip)]
+        loaded: Vec<Prefab>,
+    },
+}
+
+impl MapBuilder for MapPass {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng) {
+        match *self {
+            MapPass::CullRooms { keep } => CullRooms { keep }.build(map, rng),
+            MapPass::JoinRooms(style) => JoinRooms { style }.build(map, rng),
+            MapPass::Doors => Doors.build(map, rng),
+            MapPass::SpawnZones { count } => SpawnZones { count }.build(map, rng),
+            MapPass::Pillars { percent } => Pillars { percent }.build(map, rng),
+            MapPass::Terrain { tile, pools, size } => TerrainPools {
+                tile,
+                pools,
+                size,
+                full_floor: None,
+            }
+            .build(map, rng),
+            MapPass::Prefabs {
+                count, ref loaded, ..
+            } => StampPrefabs {
+                prefabs: loaded.clone(),
+                count,
+            }
+            .build(map, rng),
+        }
+    }
+}
+
+// Picks a generator by name with its default parameters, for choosing one on the command line
+impl FromStr for MapGenerator {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<MapGenerator, String> {
+        match name {
+            "rooms-and-corridors" => Ok(MapGenerator::RoomsAndCorridors {
+                max_rooms: 30,
+                min_size: 6,
+                max_size: 10,
+            }),
+            "bsp" => Ok(MapGenerator::Bsp {
+                min_leaf_size: 10,
+                min_room_size: 4,
+            }),
+            "caves" => Ok(MapGenerator::Caves {
+                wall_percent: 58,
+                iterations: 4,
+                max_regions: 30,
+            }),
+            "drunkards-walk" => Ok(MapGenerator::DrunkardsWalk {
+                floor_percent: 40,
+                lifetime: 400,
+            }),
+            "dla" => Ok(MapGenerator::Dla { floor_percent: 25 }),
+            _ => Err(format!(
+                "Unknown map generator {}, expected rooms-and-corridors, bsp, caves, \
+                 drunkards-walk or dla",
+                name
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct MapSettings {
+    pub width: i32,
+    pub height: i32,
+    pub generator: MapGenerator,
+    #[serde(default)]
+    pub passes: Vec<MapPass>,
+    // How many levels the dungeon goes down, each made with these settings
+    #[serde(default = "one_level")]
+    pub levels: u32,
+}
+
+fn one_level() -> u32 {
+    1
+}
+
+impl MapSettings {
+    /// Reads the map file if there is one and the files of any prefab passes.
+    pub fn load_files(&mut self) -> Result<(), String> {
+        if let MapGenerator::File { path, rows } = &mut self.generator {
+            *rows = read_map_file(Path::new(path))?;
+        }
+
+        for pass in self.passes.iter_mut() {
+            if let MapPass::Prefabs { files, loaded, .. } = pass {
+                *loaded = files
+                    .iter()
+                    .map(|file| load_prefab(Path::new(file)))
+                    .collect::<Result<_, _>>()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn prefabs(&self) -> impl Iterator<Item = &Prefab> {
+        self.passes.iter().flat_map(|pass| match pass {
+            MapPass::Prefabs { loaded, .. } => loaded.iter(),
+            _ => [].iter(),
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width < 3 || self.height < 3 {
+            return Err(format!(
+                "A {}x{} map is too small to hold any floor",
+                self.width, self.height
+            ));
+        }
+        if self.levels < 1 {
+            return Err("A dungeon needs at least one level".to_string());
+        }
+
+        match self.generator {
+            MapGenerator::RoomsAndCorridors {
+                max_rooms,
+                min_size,
+                max_size,
+            }
+            | MapGenerator::Rooms {
+                max_rooms,
+                min_size,
+                max_size,
+            } => {
+                if max_rooms < 1 {
+                    return Err("Room maps need at least one room".to_string());
+                }
+                if min_size < 1 || min_size >= max_size {
+                    return Err(format!(
+                        "Room sizes must be at least 1 and min_size ({}) must be less than max_size ({})",
+                        min_size, max_size
+                    ));
+                }
+                if max_size + 1 > self.width || max_size + 1 > self.height {
+                    return Err(format!(
+                        "Rooms up to {} tiles across don't fit on a {}x{} map",
+                        max_size, self.width, self.height
+                    ));
+                }
+            }
+            MapGenerator::Bsp {
+                min_leaf_size,
+                min_room_size,
+            } => {
+                // Rooms need a wall between them and the edge of their piece of the map
+                if min_room_size < 1 || min_room_size >= min_leaf_size {
+                    return Err(format!(
+                        "min_room_size ({}) must be at least 1 and less than min_leaf_size ({})",
+                        min_room_size, min_leaf_size
+                    ));
+                }
+                if min_leaf_size + 1 > self.width || min_leaf_size + 1 > self.height {
+                    return Err(format!(
+                        "Pieces {} tiles across don't fit on a {}x{} map",
+                        min_leaf_size, self.width, self.height
+                    ));
+                }
+            }
+            MapGenerator::Caves {
+                wall_percent,
+                max_regions,
+                ..
+            } => {
+                if !(0..100).contains(&wall_percent) {
+                    return Err(format!(
+                        "wall_percent must be from 0 to 99, not {}",
+                        wall_percent
+                    ));
+                }
+                if max_regions < 1 {
+                    return Err("Cave maps need at least one region".to_string());
+                }
+            }
+            MapGenerator::DrunkardsWalk {
+                floor_percent,
+                lifetime,
+            } => {
+                check_floor_percent(floor_percent)?;
+                if lifetime < 1 {
+                    return Err("Diggers need a lifetime of at least 1 step".to_string());
+                }
+            }
+            MapGenerator::Dla { floor_percent } => {
+                check_floor_percent(floor_percent)?;
+                // The floor starts as a cross in the middle of the map, inside the outer wall
+                if self.width < 5 || self.height < 5 {
+                    return Err(
+                        "Diffusion limited aggregation maps must be at least 5x5".to_string()
+                    );
+                }
+            }
+            MapGenerator::File { ref path, ref rows } => {
+                let file_width = rows.first().map_or(0, |row| row.chars().count()) as i32;
+                if file_width != self.width || rows.len() as i32 != self.height {
+                    return Err(format!(
+                        "{} should be {}x{} but is {}x{}",
+                        path,
+                        self.width,
+                        self.height,
+                        file_width,
+                        rows.len()
+                    ));
+                }
+
+                parse_map_rows(rows).map_err(|error| format!("{}: {}", path, error))?;
+            }
+        }
+
+        self.validate_passes()
+    }
+
+    fn validate_passes(&self) -> Result<(), String> {
+        let unjoined_rooms = matches!(self.generator, MapGenerator::Rooms { .. });
+        let mut joined = false;
+
+        for pass in self.passes.iter() {
+            match *pass {
+                MapPass::CullRooms { keep } => {
+                    if !unjoined_rooms || joined {
+                        return Err(
+                            "CullRooms would leave corridors leading nowhere, so it only \
+                             works on a Rooms map before any JoinRooms"
+                                .to_string(),
+                        );
+                    }
+                    if keep < 1 {
+                        return Err("CullRooms needs to keep at least one room".to_string());
+                    }
+                }
+                MapPass::JoinRooms(_) => joined = true,
+                MapPass::Doors => {}
+                MapPass::SpawnZones { count } => {
+                    if count < 1 {
+                        return Err("SpawnZones needs at least one zone".to_string());
+                    }
+                }
+                MapPass::Pillars { percent } => {
+                    if !(0..=100).contains(&percent) {
+                        return Err(format!(
+                            "Pillars percent must be from 0 to 100, not {}",
+                            percent
+                        ));
+                    }
+                }
+                MapPass::Terrain { tile, pools, size } => {
+                    if !tile.is_terrain() {
+                        return Err(format!(
+                            "Terrain can be ShallowWater, Rubble, Lava or Chasm, not {:?}",
+                            tile
+                        ));
+                    }
+                    if pools < 1 || size < 1 {
+                        return Err(
+                            "Terrain needs at least one pool of at least one tile".to_string()
+                        );
+                    }
+                }
+                MapPass::Prefabs {
+                    ref files, count, ..
+                } => {
+                    if files.is_empty() || count < 1 {
+                        return Err(
+                            "Prefabs needs at least one file and a count of at least 1".to_string()
+                        );
+                    }
+                }
+            }
+        }
+
+        if unjoined_rooms && !joined {
+            return Err(
+                "Rooms maps need a JoinRooms pass to dig corridors between them".to_string(),
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// Digging nearly all of the map takes generators that dig until some share of it is floor a very
+// long time
+fn check_floor_percent(floor_percent: i32) -> Result<(), String> {
+    if !(1..=80).contains(&floor_percent) {
+        return Err(format!(
+            "floor_percent must be from 1 to 80, not {}",
+            floor_percent
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct Map {
+    pub tiles: Vec<TileType>,
+    pub rooms: Vec<Rect>,
+    pub width: i32,
+    pub height: i32,
+    // Rooms picked for creatures to start in. Without any they start anywhere.
+    pub spawn_zones: Vec<Rect>,
+    // What the markers in stamped prefabs spawn. Only used when the game starts, so never saved.
+    pub prefab_spawns: Vec<(Position, PrefabSpawn)>,
+    // The living creature standing on each tile. Worked out again every tick, so never saved.
+    pub occupants: Vec<Option<Entity>>,
+    // Shared fields creatures follow downhill, also worked out again every tick
+    pub flow: FlowFields,
+    // pub revealed_tiles : Vec<bool>,
+    // pub visible_tiles : Vec<bool>
+}
+
+impl Map {
+    /// A map of solid wall for the generators to dig into.
+    pub fn new(width: i32, height: i32) -> Map {
+        Map {
+            tiles: vec![TileType::Wall; (width * height) as usize],
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    pub fn xy_idx(&self, x: i32, y: i32) -> usize {
+        (y as usize * self.width as usize) + x as usize
+    }
+
+    pub fn is_walkable(&self, idx: usize) -> bool {
+        self.tiles[idx].is_walkable()
+    }
+
+    pub fn path_cost(&self, idx: usize) -> i32 {
+        self.tiles[idx].path_cost()
+    }
+
+    /// Where creatures start: the spawn zones if the map has any, otherwise anywhere in its rooms.
+    pub fn start_zones(&self) -> &[Rect] {
+        if self.spawn_zones.is_empty() {
+            &self.rooms
+        } else {
+            &self.spawn_zones
+        }
+    }
+
+    /// Every tile a creature could start on, counted once however many zones it's in.
+    pub fn start_tiles(&self) -> Vec<usize> {
+        let mut tiles: Vec<usize> = self
+            .start_zones()
+            .iter()
+            .flat_map(|zone| {
+                (zone.y1 + 1..=zone.y2)
+                    .flat_map(move |y| (zone.x1 + 1..=zone.x2).map(move |x| (x, y)))
+            })
+            .map(|(x, y)| self.xy_idx(x, y))
+            .filter(|idx| self.tiles[*idx].can_start_on())
+            .collect();
+        tiles.sort_unstable();
+        tiles.dedup();
+        tiles
+    }
+
+    pub fn occupant(&self, idx: usize) -> Option<Entity> {
+        self.occupants.get(idx).copied().flatten()
+    }
+
+    /// Moves a creature's claim on a tile to the one it has stepped onto. The tile it left is only
+    /// freed if nobody else has stepped onto it already.
+    pub fn move_occupant(&mut self, entity: Entity, from: usize, to: usize) {
+        if self.occupants[from] == Some(entity) {
+            self.occupants[from] = None;
+        }
+        self.occupants[to] = Some(entity);
+    }
+
+    /// One string per row of the map, drawn with the same glyphs as `draw_map`.
+    pub fn to_rows(&self) -> Vec<String> {
+        self.tiles
+            .chunks(self.width as usize)
+            .map(|row| row.iter().map(tile_to_char).collect())
+            .collect()
+    }
+
+    /// Rebuilds a map from rows written by `to_rows`, checking that nothing on it can be sent
+    /// off its edge.
+    pub fn from_rows(
+        rows: &[String],
+        rooms: Vec<Rect>,
+        spawn_zones: Vec<Rect>,
+    ) -> Result<Map, String> {
+        let height = rows.len() as i32;
+        let width = rows.first().map_or(0, |row| row.chars().count()) as i32;
+        let mut tiles = Vec::with_capacity((width * height) as usize);
+
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() as i32 != width {
+                return Err(format!("Map row {} is not {} tiles wide", y, width));
+            }
+
+            for (x, glyph) in row.chars().enumerate() {
+                match char_to_tile(glyph) {
+                    Some(tile) => tiles.push(tile),
+                    None => return Err(format!("Unknown map tile '{}' at {},{}", glyph, x, y)),
+                }
+            }
+        }
+
+        let map = Map {
+            tiles,
+            rooms,
+            width,
+            height,
+            spawn_zones,
+            ..Default::default()
+        };
+
+        // Nothing can walk or see off the map, so its edge is all wall
+        for y in 0..height {
+            for x in 0..width {
+                let on_edge = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+                if on_edge && map.tiles[map.xy_idx(x, y)] != TileType::Wall {
+                    return Err(format!("The edge of the map at {},{} must be wall", x, y));
+                }
+            }
+        }
+
+        for room in map.rooms.iter().chain(map.spawn_zones.iter()) {
+            if room.x1 < 0 || room.y1 < 0 || room.x2 >= width - 1 || room.y2 >= height - 1 {
+                return Err(format!(
+                    "The room or start zone from {},{} to {},{} goes past the edge of the map",
+                    room.x1, room.y1, room.x2, room.y2
+                ));
+            }
+        }
+
+        // Creatures wander to the centre of a room, so every room needs floor there
+        let has_destinations = !map.rooms.is_empty()
+            && map.rooms.iter().all(|room| {
+                let Position(x, y) = room.center();
+                map.tiles[map.xy_idx(x, y)] == TileType::Floor
+            });
+        if !has_destinations {
+            return Err("The map has no rooms, or a room without floor in its centre".to_string());
+        }
+
+        Ok(map)
+    }
+
+    fn apply_room_to_map(&mut self, room: &Rect) {
+        for y in room.y1 + 1..=room.y2 {
+            for x in room.x1 + 1..=room.x2 {
+                let idx = self.xy_idx(x, y);
+                self.tiles[idx] = TileType::Floor;
+            }
+        }
+    }
+
+    // Tunnels never dig through the wall around the edge of the map
+    fn is_inside_edge(&self, x: i32, y: i32) -> bool {
+        x > 0 && y > 0 && x < self.width - 1 && y < self.height - 1
+    }
+
+    fn apply_horizontal_tunnel(&mut self, x1: i32, x2: i32, y: i32) {
+        for x in min(x1, x2)..=max(x1, x2) {
+            if self.is_inside_edge(x, y) {
+                let idx = self.xy_idx(x, y);
+                self.tiles[idx] = TileType::Floor;
+            }
+        }
+    }
+
+    fn apply_vertical_tunnel(&mut self, y1: i32, y2: i32, x: i32) {
+        for y in min(y1, y2)..=max(y1, y2) {
+            if self.is_inside_edge(x, y) {
+                let idx = self.xy_idx(x, y);
+                self.tiles[idx] = TileType::Floor;
+            }
+        }
+    }
+
+    // Digs an L shaped corridor between the centres of two rooms, turning the corner at random
+    fn join_rooms(&mut self, from: &Rect, to: &Rect, rng: &mut ChaCha12Rng) {
+        let Position(prev_x, prev_y) = from.center();
+        let Position(new_x, new_y) = to.center();
+
+        if rng.gen_range(0..2) == 1 {
+            self.apply_horizontal_tunnel(prev_x, new_x, prev_y);
+            self.apply_vertical_tunnel(prev_y, new_y, new_x);
+        } else {
+            self.apply_vertical_tunnel(prev_y, new_y, prev_x);
+            self.apply_horizontal_tunnel(prev_x, new_x, new_y);
+        }
+    }
+
+    /// Makes a new map with the generator picked in the settings, then runs the settings' passes
+    /// over it. Any floor that can't be reached is joined on with extra corridors, and maps that
+    /// leave creatures nowhere to wander to, or too little room for the given number of creatures
+    /// to start in, are thrown away and made again, until settings that keep making them are
+    /// given up on.
+    pub fn generate(
+        settings: &MapSettings,
+        creatures: usize,
+        rng: &mut ChaCha12Rng,
+    ) -> Result<(Map, MapReport), String> {
+        let pipeline = settings
+            .passes
+            .iter()
+            .fold(settings.generator.pipeline(), |pipeline, pass| {
+                pipeline.with(pass.clone())
+            });
+        let mut report = MapReport::default();
+
+        loop {
+            let mut map = pipeline.build(settings.width, settings.height, rng);
+            report.attempts += 1;
+            let joined = map.join_unreachable_areas();
+            report.areas_joined += joined.unwrap_or(0);
+
+            let stairs = if settings.levels > 1 && map.spawn_zones.is_empty() {
+                STAIRS_PER_LEVEL
+            } else {
+                0
+            };
+            let start_tiles = map.start_tiles().len().saturating_sub(stairs);
+            if joined.is_some() && map.has_destinations() && start_tiles >= creatures {
+                return Ok((map, report));
+            }
+            if report.attempts == MAX_MAP_ATTEMPTS {
+                let problem = if joined.is_none() {
+                    "floor walled in by ground that can't be dug through".to_string()
+                } else if map.has_destinations() {
+                    format!(
+                        "room for only {} of the {} creatures starting there",
+                        start_tiles, creatures
+                    )
+                } else {
+                    "nowhere for creatures to wander to".to_string()
+                };
+                return Err(format!(
+                    "Gave up after {} maps with {}, so the map settings need more floor",
+                    MAX_MAP_ATTEMPTS, problem
+                ));
+            }
+        }
+    }
+}
+
+impl BaseMap for Map {
+    fn is_opaque(&self, idx: usize) -> bool {
+        matches!(
+            self.tiles[idx],
+            TileType::Wall | TileType::Door { open: false }
+        )
+    }
+}
+
+impl Algorithm2D for Map {
+    fn dimensions(&self) -> Point {
+        Point::new(self.width, self.height)
+    }
+}
+
+pub fn tile_to_char(tile: &TileType) -> &str {
+    match tile {
+        TileType::Floor => ".",
+        TileType::Wall => "#",
+        TileType::Door { open: false } => "+",
+        TileType::Door { open: true } => "'",
+        TileType::ShallowWater => "~",
+        TileType::Rubble => ":",
+        TileType::Lava => "^",
+        TileType::Chasm => "_",
+        TileType::DownStairs => ">",
+        TileType::UpStairs => "<",
+    }
+}
+
+pub fn char_to_tile(glyph: char) -> Option<TileType> {
+    match glyph {
+        '.' => Some(TileType::Floor),
+        '#' => Some(TileType::Wall),
+        '+' => Some(TileType::Door { open: false }),
+        '\'' => Some(TileType::Door { open: true }),
+        '~' => Some(TileType::ShallowWater),
+        ':' => Some(TileType::Rubble),
+        '^' => Some(TileType::Lava),
+        '_' => Some(TileType::Chasm),
+        '>' => Some(TileType::DownStairs),
+        '<' => Some(TileType::UpStairs),
+        _ => None,
+    }
+}
+
+pub fn tile_colour(tile: &TileType) -> style::Color {
+    match tile {
+        TileType::Wall | TileType::Floor | TileType::Chasm => style::Color::DarkGrey,
+        TileType::Door { .. } => style::Color::DarkYellow,
+        TileType::ShallowWater => style::Color::Blue,
+        TileType::Rubble => style::Color::Grey,
+        TileType::Lava => style::Color::Red,
+        TileType::DownStairs | TileType::UpStairs => style::Color::White,
+    }
+}
+
+pub fn draw_map(dungeon: Res<Dungeon>, shown_level: Res<ShownLevel>) {
+    let mut stdout = stdout();
+    let mut colour = None;
+    let map = dungeon.map(&shown_level.0);
+
+    // Each row is positioned explicitly so the map also draws correctly in raw mode, where a
+    // newline doesn't return the cursor to the start of the line
+    for y in 0..map.height {
+        stdout.queue(cursor::MoveTo(0, y as u16)).unwrap();
+
+        for x in 0..map.width {
+            let tile = &map.tiles[map.xy_idx(x, y)];
+
+            // Only changing colour when it's needed keeps the output down
+            if colour != Some(tile_colour(tile)) {
+                colour = Some(tile_colour(tile));
+                stdout
+                    .queue(style::SetForegroundColor(tile_colour(tile)))
+                    .unwrap();
+            }
+
+            stdout.queue(style::Print(tile_to_char(tile))).unwrap();
+        }
+    }
+
+    // Above the log, which starts a couple of rows down
+    if dungeon.levels.len() > 1 {
+        stdout
+            .queue(cursor::MoveTo((map.width + 1) as u16, 0))
+            .unwrap()
+            .queue(style::SetForegroundColor(style::Color::White))
+            .unwrap()
+            .queue(style::Print(format!(
+                "Level {} of {}",
+                shown_level.0,
+                dungeon.levels.len()
+            )))
+            .unwrap();
+    }
+}
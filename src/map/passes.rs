@@ -0,0 +1,535 @@
+use rand::{prelude::SliceRandom, seq::index, Rng};
+use rand_chacha::ChaCha12Rng;
+use serde::Deserialize;
+
+use super::{builder::MapBuilder, Map, TileType};
+use crate::{
+    position::{distance2d_pythagoras_squared, Position},
+    rect::Rect,
+};
+
+/// Fills some of the rooms back in at random until only `keep` are left. Corridors dug before
+/// this would be left leading nowhere, so it goes before `JoinRooms`.
+pub struct CullRooms {
+    pub keep: u32,
+}
+
+impl MapBuilder for CullRooms {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng) {
+        let keep = self.keep as usize;
+        if map.rooms.len() <= keep {
+            return;
+        }
+
+        // Sorted so the rooms that stay keep the order they were dug in
+        let mut kept = index::sample(rng, map.rooms.len(), keep).into_vec();
+        kept.sort_unstable();
+
+        let rooms = std::mem::take(&mut map.rooms);
+        for (i, room) in rooms.into_iter().enumerate() {
+            if kept.contains(&i) {
+                map.rooms.push(room);
+            } else {
+                for y in room.y1 + 1..=room.y2 {
+                    for x in room.x1 + 1..=room.x2 {
+                        let idx = map.xy_idx(x, y);
+                        map.tiles[idx] = TileType::Wall;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize)]
+pub enum CorridorStyle {
+    // Each room is joined to the one dug before it, which gives long corridors criss-crossing
+    // the map
+    Sequential,
+    // Each room is joined to the closest room that is already joined up, which gives short
+    // corridors between neighbours
+    Nearest,
+}
+
+/// Digs corridors between the rooms so every room can be reached from every other.
+pub struct JoinRooms {
+    pub style: CorridorStyle,
+}
+
+impl MapBuilder for JoinRooms {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng) {
+        let rooms = map.rooms.clone();
+
+        match self.style {
+            CorridorStyle::Sequential => {
+                for pair in rooms.windows(2) {
+                    map.join_rooms(&pair[0], &pair[1], rng);
+                }
+            }
+            CorridorStyle::Nearest => {
+                let mut joined = vec![false; rooms.len()];
+                if let Some(first) = joined.first_mut() {
+                    *first = true;
+                }
+
+                for _ in 1..rooms.len() {
+                    let closest = (0..rooms.len())
+                        .filter(|from| joined[*from])
+                        .flat_map(|from| {
+                            (0..rooms.len())
+                                .filter(|to| !joined[*to])
+                                .map(move |to| (from, to))
+                        })
+                        .min_by(|(a_from, a_to), (b_from, b_to)| {
+                            let a = distance2d_pythagoras_squared(
+                                &rooms[*a_from].center(),
+                                &rooms[*a_to].center(),
+                            );
+                            let b = distance2d_pythagoras_squared(
+                                &rooms[*b_from].center(),
+                                &rooms[*b_to].center(),
+                            );
+                            a.partial_cmp(&b).unwrap()
+                        });
+
+                    if let Some((from, to)) = closest {
+                        map.join_rooms(&rooms[from], &rooms[to], rng);
+                        joined[to] = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Hangs closed doors in the gaps one tile wide where a corridor runs into a room.
+pub struct Doors;
+
+impl MapBuilder for Doors {
+    fn build(&self, map: &mut Map, _rng: &mut ChaCha12Rng) {
+        let is_wall = |map: &Map, x: i32, y: i32| map.tiles[map.xy_idx(x, y)] == TileType::Wall;
+        let mut sites = Vec::new();
+
+        for room in map.rooms.iter() {
+            // The ring of tiles just outside the room's floor, leaving out the corners. Along the
+            // top and bottom a doorway has wall to its left and right, and along the sides it has
+            // wall above and below.
+            let mut candidates = Vec::new();
+            for x in room.x1 + 1..=room.x2 {
+                candidates.push((x, room.y1, true));
+                candidates.push((x, room.y2 + 1, true));
+            }
+            for y in room.y1 + 1..=room.y2 {
+                candidates.push((room.x1, y, false));
+                candidates.push((room.x2 + 1, y, false));
+            }
+
+            for (x, y, across) in candidates {
+                let on_edge = x < 1 || y < 1 || x > map.width - 2 || y > map.height - 2;
+                if on_edge || map.tiles[map.xy_idx(x, y)] != TileType::Floor {
+                    continue;
+                }
+
+                let doorway = if across {
+                    is_wall(map, x - 1, y) && is_wall(map, x + 1, y)
+                } else {
+                    is_wall(map, x, y - 1) && is_wall(map, x, y + 1)
+                };
+
+                if doorway {
+                    sites.push(map.xy_idx(x, y));
+                }
+            }
+        }
+
+        // Doors are hung after every room is checked, so a door never stops another being found
+        for idx in sites {
+            map.tiles[idx] = TileType::Door { open: false };
+        }
+    }
+}
+
+/// Picks `count` rooms spread out across the map for creatures to start in, so the battle doesn't
+/// begin with everyone on top of each other.
+pub struct SpawnZones {
+    pub count: u32,
+}
+
+impl MapBuilder for SpawnZones {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng) {
+        let first = match map.rooms.choose(rng) {
+            Some(room) => room.clone(),
+            None => return,
+        };
+        let mut zones = vec![first];
+
+        // Each zone after the first is the room furthest from all the zones picked so far
+        while zones.len() < self.count as usize && zones.len() < map.rooms.len() {
+            let furthest = map.rooms.iter().max_by(|a, b| {
+                distance_to_nearest(a, &zones)
+                    .partial_cmp(&distance_to_nearest(b, &zones))
+                    .unwrap()
+            });
+
+            match furthest {
+                Some(room) => zones.push(room.clone()),
+                None => break,
+            }
+        }
+
+        map.spawn_zones = zones;
+    }
+}
+
+fn distance_to_nearest(room: &Rect, zones: &[Rect]) -> f32 {
+    zones
+        .iter()
+        .map(|zone| distance2d_pythagoras_squared(&zone.center(), &room.center()))
+        .fold(f32::MAX, f32::min)
+}
+
+/// Scatters pools of terrain over the floor. Each pool spreads out at random from a tile of floor
+/// to up to `size` tiles, leaving room centres and prefab spawn markers as floor so creatures can
+/// always start somewhere and have somewhere to wander to. Chasms can cut the map in two, which
+/// the check after building repairs.
+pub struct TerrainPools {
+    pub tile: TileType,
+    pub pools: u32,
+    pub size: u32,
+    // Maps with less floor than this to cover get fewer pools in proportion, so terrain takes up
+    // no more of a small map than it would of this much floor. Without it every pool goes in.
+    pub full_floor: Option<u32>,
+}
+
+impl MapBuilder for TerrainPools {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng) {
+        let protected: Vec<usize> = map
+            .rooms
+            .iter()
+            .map(|room| room.center())
+            .chain(map.prefab_spawns.iter().map(|(spot, _)| spot.clone()))
+            .map(|Position(x, y)| map.xy_idx(x, y))
+            .collect();
+        let can_cover =
+            |map: &Map, idx: usize| map.tiles[idx] == TileType::Floor && !protected.contains(&idx);
+
+        let floor: Vec<usize> = (0..map.tiles.len())
+            .filter(|idx| can_cover(map, *idx))
+            .collect();
+
+        let pools = match self.full_floor {
+            Some(full_floor) if (floor.len() as u32) < full_floor => {
+                self.pools * floor.len() as u32 / full_floor
+            }
+            _ => self.pools,
+        };
+
+        for _ in 0..pools {
+            let start = match floor.choose(rng) {
+                Some(idx) => *idx,
+                None => return,
+            };
+
+            let mut edge = vec![start];
+            let mut covered = 0;
+            while covered < self.size && !edge.is_empty() {
+                let idx = edge.swap_remove(rng.gen_range(0..edge.len()));
+                if !can_cover(map, idx) {
+                    continue;
+                }
+
+                map.tiles[idx] = self.tile;
+                covered += 1;
+
+                let width = map.width as usize;
+                for neighbour in [idx - 1, idx + 1, idx - width, idx + width].iter() {
+                    if can_cover(map, *neighbour) {
+                        edge.push(*neighbour);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Decorates rooms with single pillars of wall. A pillar only goes where all eight tiles around it
+/// are floor, so pillars never touch each other and can't cut the room in two, and never on a
+/// room's centre, which creatures wander to, or on a prefab's spawn marker.
+pub struct Pillars {
+    // Chance out of 100 of each tile that has room for one getting a pillar
+    pub percent: i32,
+}
+
+impl MapBuilder for Pillars {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng) {
+        let rooms = map.rooms.clone();
+
+        for room in rooms.iter() {
+            let centre = room.center();
+
+            for y in room.y1 + 1..=room.y2 {
+                for x in room.x1 + 1..=room.x2 {
+                    let on_edge = x < 1 || y < 1 || x > map.width - 2 || y > map.height - 2;
+                    let spawn_here = map
+                        .prefab_spawns
+                        .iter()
+                        .any(|(spot, _)| *spot == Position(x, y));
+                    if on_edge || spawn_here || Position(x, y) == centre {
+                        continue;
+                    }
+
+                    let open = (-1..=1).all(|dy| {
+                        (-1..=1).all(|dx| map.tiles[map.xy_idx(x + dx, y + dy)] == TileType::Floor)
+                    });
+
+                    if open && rng.gen_range(0..100) < self.percent {
+                        let idx = map.xy_idx(x, y);
+                        map.tiles[idx] = TileType::Wall;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::{checks::floor_area_count, rooms::RandomRoomsBuilder};
+
+    const WIDTH: i32 = 60;
+    const HEIGHT: i32 = 40;
+
+    // Rooms that nothing has joined up yet
+    fn rooms_map(seed: u64) -> (Map, ChaCha12Rng) {
+        let mut map = Map::new(WIDTH, HEIGHT);
+        let mut rng = ChaCha12Rng::seed_from_u64(seed);
+        RandomRoomsBuilder {
+            max_rooms: 20,
+            min_size: 6,
+            max_size: 10,
+        }
+        .build(&mut map, &mut rng);
+        (map, rng)
+    }
+
+    fn joined_map(seed: u64) -> (Map, ChaCha12Rng) {
+        let (mut map, mut rng) = rooms_map(seed);
+        JoinRooms {
+            style: CorridorStyle::Sequential,
+        }
+        .build(&mut map, &mut rng);
+        (map, rng)
+    }
+
+    fn tile_at(map: &Map, Position(x, y): Position) -> TileType {
+        map.tiles[map.xy_idx(x, y)]
+    }
+
+    fn in_room(room: &Rect, x: i32, y: i32) -> bool {
+        x > room.x1 && x <= room.x2 && y > room.y1 && y <= room.y2
+    }
+
+    #[test]
+    fn cull_rooms_walls_up_all_but_the_kept_rooms() {
+        for seed in 0..10 {
+            let (mut map, mut rng) = rooms_map(seed);
+            assert!(map.rooms.len() > 3);
+
+            CullRooms { keep: 3 }.build(&mut map, &mut rng);
+
+            assert_eq!(map.rooms.len(), 3);
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    if map.tiles[map.xy_idx(x, y)] == TileType::Floor {
+                        assert!(map.rooms.iter().any(|room| in_room(room, x, y)));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cull_rooms_leaves_maps_with_few_enough_rooms_alone() {
+        let (mut map, mut rng) = rooms_map(1);
+        let tiles = map.tiles.clone();
+        let rooms = map.rooms.len();
+
+        CullRooms { keep: 100 }.build(&mut map, &mut rng);
+
+        assert_eq!(map.rooms.len(), rooms);
+        assert_eq!(map.tiles, tiles);
+    }
+
+    #[test]
+    fn join_rooms_reaches_every_room() {
+        for style in [CorridorStyle::Sequential, CorridorStyle::Nearest].iter() {
+            for seed in 0..10 {
+                let (mut map, mut rng) = rooms_map(seed);
+                JoinRooms { style: *style }.build(&mut map, &mut rng);
+
+                assert_eq!(floor_area_count(&map), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn doors_only_go_in_corridor_mouths() {
+        let mut doors = 0;
+
+        for seed in 0..10 {
+            let (mut map, mut rng) = joined_map(seed);
+            let before = map.tiles.clone();
+            Doors.build(&mut map, &mut rng);
+
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let idx = map.xy_idx(x, y);
+                    if !matches!(map.tiles[idx], TileType::Door { .. }) {
+                        assert_eq!(map.tiles[idx], before[idx]);
+                        continue;
+                    }
+                    doors += 1;
+
+                    // Dug out of the wall just outside a room, with floor through it and wall
+                    // either side
+                    assert_eq!(before[idx], TileType::Floor);
+                    assert!(!map.rooms.iter().any(|room| in_room(room, x, y)));
+                    let tile = |x: i32, y: i32| map.tiles[map.xy_idx(x, y)];
+                    let walled_across = tile(x - 1, y) == TileType::Wall
+                        && tile(x + 1, y) == TileType::Wall
+                        && tile(x, y - 1).is_walkable()
+                        && tile(x, y + 1).is_walkable();
+                    let walled_along = tile(x, y - 1) == TileType::Wall
+                        && tile(x, y + 1) == TileType::Wall
+                        && tile(x - 1, y).is_walkable()
+                        && tile(x + 1, y).is_walkable();
+                    assert!(walled_across || walled_along);
+                    assert!(map.rooms.iter().any(|room| {
+                        in_room(room, x, y - 1)
+                            || in_room(room, x, y + 1)
+                            || in_room(room, x - 1, y)
+                            || in_room(room, x + 1, y)
+                    }));
+                }
+            }
+
+            // Doors can be walked through, so every room can still be reached
+            let (_, area_sizes) = map.floor_areas();
+            assert_eq!(area_sizes.len(), 1);
+        }
+
+        assert!(doors > 0);
+    }
+
+    #[test]
+    fn spawn_zones_are_different_rooms() {
+        for seed in 0..10 {
+            let (mut map, mut rng) = joined_map(seed);
+            SpawnZones { count: 3 }.build(&mut map, &mut rng);
+
+            assert_eq!(map.spawn_zones.len(), 3);
+            for (i, zone) in map.spawn_zones.iter().enumerate() {
+                assert!(map.rooms.iter().any(|room| room.center() == zone.center()));
+                assert!(map.spawn_zones[..i]
+                    .iter()
+                    .all(|other| other.center() != zone.center()));
+            }
+        }
+    }
+
+    #[test]
+    fn spawn_zones_stop_at_the_number_of_rooms() {
+        let (mut map, mut rng) = joined_map(2);
+        SpawnZones { count: 100 }.build(&mut map, &mut rng);
+        assert_eq!(map.spawn_zones.len(), map.rooms.len());
+    }
+
+    #[test]
+    fn pillars_stand_alone_off_room_centres() {
+        for seed in 0..10 {
+            let (mut map, mut rng) = joined_map(seed);
+            let before = map.tiles.clone();
+            Pillars { percent: 50 }.build(&mut map, &mut rng);
+
+            let mut pillars = 0;
+            for y in 0..HEIGHT {
+                for x in 0..WIDTH {
+                    let idx = map.xy_idx(x, y);
+                    if map.tiles[idx] == before[idx] {
+                        continue;
+                    }
+                    pillars += 1;
+
+                    assert_eq!(map.tiles[idx], TileType::Wall);
+                    assert!(map.rooms.iter().any(|room| in_room(room, x, y)));
+                    for dy in -1..=1 {
+                        for dx in -1..=1 {
+                            if dx != 0 || dy != 0 {
+                                assert_eq!(map.tiles[map.xy_idx(x + dx, y + dy)], TileType::Floor);
+                            }
+                        }
+                    }
+                }
+            }
+
+            assert!(pillars > 0);
+            for room in map.rooms.iter() {
+                assert_eq!(tile_at(&map, room.center()), TileType::Floor);
+            }
+            assert_eq!(floor_area_count(&map), 1);
+        }
+    }
+
+    #[test]
+    fn terrain_pools_only_cover_floor_and_leave_room_centres() {
+        for seed in 0..10 {
+            let (mut map, mut rng) = joined_map(seed);
+            let before = map.tiles.clone();
+            TerrainPools {
+                tile: TileType::Lava,
+                pools: 4,
+                size: 10,
+                full_floor: None,
+            }
+            .build(&mut map, &mut rng);
+
+            let covered = (0..map.tiles.len())
+                .filter(|idx| map.tiles[*idx] != before[*idx])
+                .inspect(|idx| {
+                    assert_eq!(before[*idx], TileType::Floor);
+                    assert_eq!(map.tiles[*idx], TileType::Lava);
+                })
+                .count();
+
+            assert!(covered > 0 && covered <= 4 * 10);
+            for room in map.rooms.iter() {
+                assert_eq!(tile_at(&map, room.center()), TileType::Floor);
+            }
+        }
+    }
+
+    #[test]
+    fn terrain_pools_are_scaled_down_on_maps_with_little_floor() {
+        let (mut map, mut rng) = joined_map(3);
+        let floor = map
+            .tiles
+            .iter()
+            .filter(|tile| **tile == TileType::Floor)
+            .count() as u32;
+        let before = map.tiles.clone();
+
+        // With a fifth of the floor the pools are meant for, four pools round down to none
+        TerrainPools {
+            tile: TileType::Rubble,
+            pools: 4,
+            size: 10,
+            full_floor: Some(floor * 5),
+        }
+        .build(&mut map, &mut rng);
+
+        assert_eq!(map.tiles, before);
+    }
+}
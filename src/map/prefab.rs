@@ -0,0 +1,322 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    path::Path,
+};
+
+use rand::prelude::SliceRandom;
+use rand_chacha::ChaCha12Rng;
+use serde::Deserialize;
+
+use super::{builder::MapBuilder, char_to_tile, dungeon::check_stairs, Map, TileType};
+use crate::position::Position;
+
+// What a marker in a prefab spawns on its tile
+#[derive(Clone, Deserialize)]
+pub enum PrefabSpawn {
+    // The id of a creature template
+    Creature(String),
+    // The name of an item in the item catalogue
+    Item(String),
+}
+
+/// A hand drawn piece of map, such as a treasure vault or an arena, read from a prefab file. Rows
+/// use the same glyphs as the map is drawn with, plus marker glyphs for floor tiles that something
+/// spawns on.
+#[derive(Clone, Deserialize)]
+pub struct Prefab {
+    pub name: String,
+    pub rows: Vec<String>,
+    #[serde(default)]
+    pub markers: HashMap<char, PrefabSpawn>,
+}
+
+impl Prefab {
+    fn width(&self) -> i32 {
+        self.rows[0].chars().count() as i32
+    }
+
+    fn height(&self) -> i32 {
+        self.rows.len() as i32
+    }
+
+    fn glyph(&self, x: i32, y: i32) -> char {
+        self.rows[y as usize].chars().nth(x as usize).unwrap()
+    }
+
+    // Markers always stand on floor
+    fn tile(&self, x: i32, y: i32) -> TileType {
+        char_to_tile(self.glyph(x, y)).unwrap_or(TileType::Floor)
+    }
+
+    fn tiles(&self) -> impl Iterator<Item = TileType> + '_ {
+        (0..self.height()).flat_map(move |y| (0..self.width()).map(move |x| self.tile(x, y)))
+    }
+
+    // Every tile of floor has to be reachable from outside the prefab once it's stamped, or
+    // anything spawned inside would be stuck
+    fn check_connected(&self) -> Result<(), String> {
+        let (width, height) = (self.width(), self.height());
+        let is_walkable = |x: i32, y: i32| self.tile(x, y).is_walkable();
+        let mut reached = vec![false; (width * height) as usize];
+        let mut open = VecDeque::new();
+
+        for y in 0..height {
+            for x in 0..width {
+                let on_edge = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+                if on_edge && is_walkable(x, y) {
+                    reached[(y * width + x) as usize] = true;
+                    open.push_back((x, y));
+                }
+            }
+        }
+
+        while let Some((x, y)) = open.pop_front() {
+            for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)].iter() {
+                let (nx, ny) = (*nx, *ny);
+                if nx < 0 || ny < 0 || nx >= width || ny >= height || !is_walkable(nx, ny) {
+                    continue;
+                }
+                let idx = (ny * width + nx) as usize;
+                if !reached[idx] {
+                    reached[idx] = true;
+                    open.push_back((nx, ny));
+                }
+            }
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                if is_walkable(x, y) && !reached[(y * width + x) as usize] {
+                    return Err(format!(
+                        "the tile at {},{} can't be reached from the edge of the prefab",
+                        x, y
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let width = match self.rows.first() {
+            Some(row) if !row.is_empty() => row.chars().count(),
+            _ => return Err("prefabs need at least one tile".to_string()),
+        };
+
+        for glyph in self.markers.keys() {
+            if char_to_tile(*glyph).is_some() {
+                return Err(format!("'{}' is a map tile so it can't be a marker", glyph));
+            }
+        }
+
+        for (y, row) in self.rows.iter().enumerate() {
+            if row.chars().count() != width {
+                return Err(format!("row {} is not {} tiles wide", y, width));
+            }
+
+            for (x, glyph) in row.chars().enumerate() {
+                if char_to_tile(glyph).is_none() && !self.markers.contains_key(&glyph) {
+                    return Err(format!("unknown tile '{}' at {},{}", glyph, x, y));
+                }
+            }
+        }
+
+        check_stairs(self.tiles()).map_err(|error| format!("the prefab has {}", error))?;
+
+        self.check_connected()
+    }
+}
+
+pub fn load_prefab(path: &Path) -> Result<Prefab, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+
+    let prefab: Prefab = ron::de::from_str(&contents)
+        .map_err(|error| format!("Could not parse {}: {}", path.display(), error))?;
+
+    prefab
+        .validate()
+        .map_err(|error| format!("{}: {}", path.display(), error))?;
+
+    Ok(prefab)
+}
+
+/// Stamps up to `count` prefabs, picked at random, into open floor. A prefab only goes where it
+/// and a tile of floor all around it are already floor, so it never cuts the map in two, and any
+/// room centre it covers has to land on floor in the prefab. Prefabs that don't fit anywhere, or
+/// whose stairs the map already has, are left out.
+pub struct StampPrefabs {
+    pub prefabs: Vec<Prefab>,
+    pub count: u32,
+}
+
+impl MapBuilder for StampPrefabs {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng) {
+        // Tiles already stamped, so prefabs don't land on top of each other
+        let mut stamped = vec![false; map.tiles.len()];
+
+        for _ in 0..self.count {
+            let prefab = match self.prefabs.choose(rng) {
+                Some(prefab) => prefab,
+                None => return,
+            };
+
+            // A level only has one set of stairs going each way
+            let stairs = [TileType::DownStairs, TileType::UpStairs];
+            if prefab
+                .tiles()
+                .any(|tile| stairs.contains(&tile) && map.tiles.contains(&tile))
+            {
+                continue;
+            }
+
+            let spots: Vec<Position> = (1..map.height - prefab.height())
+                .flat_map(|y| (1..map.width - prefab.width()).map(move |x| Position(x, y)))
+                .filter(|spot| map.fits_prefab(prefab, spot, &stamped))
+                .collect();
+
+            if let Some(Position(left, top)) = spots.choose(rng) {
+                for y in 0..prefab.height() {
+                    for x in 0..prefab.width() {
+                        let idx = map.xy_idx(left + x, top + y);
+                        map.tiles[idx] = prefab.tile(x, y);
+                        stamped[idx] = true;
+
+                        if let Some(spawn) = prefab.markers.get(&prefab.glyph(x, y)) {
+                            map.prefab_spawns
+                                .push((Position(left + x, top + y), spawn.clone()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Map {
+    fn fits_prefab(&self, prefab: &Prefab, spot: &Position, stamped: &[bool]) -> bool {
+        let Position(left, top) = *spot;
+
+        for y in top - 1..=top + prefab.height() {
+            for x in left - 1..=left + prefab.width() {
+                let idx = self.xy_idx(x, y);
+                if self.tiles[idx] != TileType::Floor || stamped[idx] {
+                    return false;
+                }
+            }
+        }
+
+        // Creatures wander to room centres
+        self.rooms
+            .iter()
+            .map(|room| room.center())
+            .all(|Position(x, y)| {
+                let inside =
+                    x >= left && y >= top && x < left + prefab.width() && y < top + prefab.height();
+                !inside || prefab.tile(x - left, y - top) == TileType::Floor
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::{
+        checks::floor_area_count,
+        passes::{CorridorStyle, JoinRooms},
+        rooms::RandomRoomsBuilder,
+    };
+
+    fn prefab(rows: &[&str], markers: &[(char, PrefabSpawn)]) -> Prefab {
+        Prefab {
+            name: "Test".to_string(),
+            rows: rows.iter().map(|row| row.to_string()).collect(),
+            markers: markers.iter().cloned().collect(),
+        }
+    }
+
+    // A small vault with a doorway on each side and a goblin waiting in the middle
+    fn vault() -> Prefab {
+        prefab(
+            &["##.##", "#...#", "..g..", "#...#", "##.##"],
+            &[('g', PrefabSpawn::Creature("goblin".to_string()))],
+        )
+    }
+
+    #[test]
+    fn the_prefab_files_load() {
+        for file in [
+            "assets/prefabs/guard_room.ron",
+            "assets/prefabs/treasure_vault.ron",
+        ]
+        .iter()
+        {
+            assert!(load_prefab(Path::new(file)).is_ok(), "{}", file);
+        }
+    }
+
+    #[test]
+    fn bad_prefabs_are_rejected() {
+        let goblin = ('g', PrefabSpawn::Creature("goblin".to_string()));
+        // A marker can't use a glyph that's already a tile
+        let dagger = ('.', PrefabSpawn::Item("Dagger".to_string()));
+
+        assert!(vault().validate().is_ok());
+        assert!(prefab(&[], &[]).validate().is_err());
+        assert!(prefab(&["##.", "#."], &[]).validate().is_err());
+        assert!(prefab(&["#?#", "..."], &[]).validate().is_err());
+        assert!(prefab(&["#.#", "..."], &[dagger]).validate().is_err());
+        assert!(prefab(&["<.<", "..."], &[]).validate().is_err());
+        // The goblin is walled in
+        assert!(prefab(&["#####", "##g##", "#####", "....."], &[goblin])
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn stamped_prefabs_keep_the_map_in_one_piece() {
+        let vault = vault();
+
+        for seed in 0..10 {
+            let mut map = Map::new(60, 40);
+            let mut rng = ChaCha12Rng::seed_from_u64(seed);
+            RandomRoomsBuilder {
+                max_rooms: 20,
+                min_size: 8,
+                max_size: 12,
+            }
+            .build(&mut map, &mut rng);
+            JoinRooms {
+                style: CorridorStyle::Nearest,
+            }
+            .build(&mut map, &mut rng);
+            let before = map.tiles.clone();
+
+            StampPrefabs {
+                prefabs: vec![vault.clone()],
+                count: 2,
+            }
+            .build(&mut map, &mut rng);
+
+            // Each marker is left as floor in the middle of a copy of the prefab, which was
+            // stamped over what used to be floor
+            assert!(!map.prefab_spawns.is_empty());
+            for (Position(x, y), spawn) in map.prefab_spawns.iter() {
+                assert!(matches!(spawn, PrefabSpawn::Creature(id) if id == "goblin"));
+                for dy in 0..vault.height() {
+                    for dx in 0..vault.width() {
+                        let idx = map.xy_idx(x - 2 + dx, y - 2 + dy);
+                        assert_eq!(map.tiles[idx], vault.tile(dx, dy));
+                        assert_eq!(before[idx], TileType::Floor);
+                    }
+                }
+            }
+
+            assert_eq!(floor_area_count(&map), 1);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+
+use rand_chacha::ChaCha12Rng;
+
+use super::{builder::MapBuilder, Map, TileType};
+use crate::rect::Rect;
+
+// Regions smaller than this across either way are too cramped to be worth wandering to
+const MIN_REGION_SIZE: i32 = 2;
+
+/// Walls up every pocket of floor that can't be reached from the biggest one.
+pub struct KeepLargestArea;
+
+impl MapBuilder for KeepLargestArea {
+    fn build(&self, map: &mut Map, _rng: &mut ChaCha12Rng) {
+        map.fill_unreachable_pockets();
+    }
+}
+
+/// Replaces the map's rooms with open rectangles of floor, for maps that were dug without any.
+pub struct FindRegions {
+    pub max_regions: u32,
+}
+
+impl MapBuilder for FindRegions {
+    fn build(&self, map: &mut Map, _rng: &mut ChaCha12Rng) {
+        map.rooms = map.find_regions(self.max_regions);
+    }
+}
+
+impl Map {
+    // Splits the floor and doors into areas that can be walked between, returning which area each
+    // tile is in and how big each area is. Tiles on the edge of the map have to be walls.
+    pub(super) fn floor_areas(&self) -> (Vec<Option<usize>>, Vec<usize>) {
+        let mut area_of: Vec<Option<usize>> = vec![None; self.tiles.len()];
+        let mut area_sizes: Vec<usize> = Vec::new();
+
+        for start in 0..self.tiles.len() {
+            if !self.tiles[start].is_walkable() || area_of[start].is_some() {
+                continue;
+            }
+
+            let area = area_sizes.len();
+            let mut size = 0;
+            let mut open = VecDeque::new();
+            area_of[start] = Some(area);
+            open.push_back(start);
+
+            while let Some(idx) = open.pop_front() {
+                size += 1;
+
+                let x = idx as i32 % self.width;
+                let y = idx as i32 / self.width;
+                for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)].iter() {
+                    let neighbour = self.xy_idx(*nx, *ny);
+                    if self.tiles[neighbour].is_walkable() && area_of[neighbour].is_none() {
+                        area_of[neighbour] = Some(area);
+                        open.push_back(neighbour);
+                    }
+                }
+            }
+
+            area_sizes.push(size);
+        }
+
+        (area_of, area_sizes)
+    }
+
+    // Keeps the biggest connected area of floor and walls up everything else, so every floor tile
+    // can be reached from every other
+    fn fill_unreachable_pockets(&mut self) {
+        let (area_of, area_sizes) = self.floor_areas();
+        let biggest = (0..area_sizes.len()).max_by_key(|area| area_sizes[*area]);
+
+        for (idx, area) in area_of.iter().enumerate() {
+            if area.is_some() && *area != biggest {
+                self.tiles[idx] = TileType::Wall;
+            }
+        }
+    }
+
+    // Maps without rooms get regions instead: the biggest rectangles of open floor, picked out one
+    // at a time with a gap around each so they spread out across the map
+    pub(super) fn find_regions(&self, max_regions: u32) -> Vec<Rect> {
+        let mut claimed = vec![false; self.tiles.len()];
+        let mut regions = Vec::new();
+
+        while (regions.len() as u32) < max_regions {
+            let open: Vec<bool> = self
+                .tiles
+                .iter()
+                .zip(claimed.iter())
+                .map(|(tile, claimed)| *tile == TileType::Floor && !claimed)
+                .collect();
+
+            let (x1, y1, x2, y2) = match self.largest_open_rectangle(&open, MIN_REGION_SIZE) {
+                Some(rectangle) => rectangle,
+                None => break,
+            };
+
+            for y in (y1 - 1).max(0)..=(y2 + 1).min(self.height - 1) {
+                for x in (x1 - 1).max(0)..=(x2 + 1).min(self.width - 1) {
+                    claimed[self.xy_idx(x, y)] = true;
+                }
+            }
+
+            // Rooms count their floor from one tile in from their top left corner
+            regions.push(Rect::new(x1 - 1, y1 - 1, x2 - x1 + 1, y2 - y1 + 1));
+        }
+
+        regions
+    }
+
+    // Returns the corners of the biggest rectangle made up of only open tiles and at least
+    // min_size across both ways, using the running column heights of each row as a histogram
+    fn largest_open_rectangle(&self, open: &[bool], min_size: i32) -> Option<(i32, i32, i32, i32)> {
+        let mut heights = vec![0; self.width as usize];
+        let mut best: Option<(i32, (i32, i32, i32, i32))> = None;
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let idx = self.xy_idx(x, y);
+                heights[x as usize] = if open[idx] {
+                    heights[x as usize] + 1
+                } else {
+                    0
+                };
+            }
+
+            // Columns whose height hasn't been beaten yet, with where their rectangle starts
+            let mut stack: Vec<(i32, i32)> = Vec::new();
+            for x in 0..=self.width {
+                let height = if x < self.width {
+                    heights[x as usize]
+                } else {
+                    0
+                };
+                let mut start = x;
+
+                while let Some(&(stack_start, stack_height)) = stack.last() {
+                    if stack_height < height {
+                        break;
+                    }
+                    stack.pop();
+
+                    let width = x - stack_start;
+                    let area = stack_height * width;
+                    let big_enough = width >= min_size && stack_height >= min_size;
+                    let biggest = match best {
+                        Some((best_area, _)) => area > best_area,
+                        None => true,
+                    };
+                    if big_enough && biggest {
+                        best = Some((area, (stack_start, y - stack_height + 1, x - 1, y)));
+                    }
+                    start = stack_start;
+                }
+
+                stack.push((start, height));
+            }
+        }
+
+        best.map(|(_, rectangle)| rectangle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::{caves::CavesBuilder, checks::floor_area_count};
+
+    const MAX_REGIONS: u32 = 8;
+
+    // Caves dug without anything joining them up, which leaves pockets cut off from each other
+    fn cave_map(seed: u64) -> Map {
+        let mut map = Map::new(60, 40);
+        CavesBuilder {
+            wall_percent: 55,
+            iterations: 4,
+        }
+        .build(&mut map, &mut ChaCha12Rng::seed_from_u64(seed));
+        map
+    }
+
+    fn floor_tiles(map: &Map) -> usize {
+        map.tiles
+            .iter()
+            .filter(|tile| **tile == TileType::Floor)
+            .count()
+    }
+
+    #[test]
+    fn keep_largest_area_walls_up_the_other_pockets() {
+        let mut rng = ChaCha12Rng::seed_from_u64(0);
+
+        for seed in 0..20 {
+            let mut map = cave_map(seed);
+            let pockets = floor_area_count(&map);
+            let floor = floor_tiles(&map);
+
+            KeepLargestArea.build(&mut map, &mut rng);
+
+            assert_eq!(floor_area_count(&map), 1);
+            assert!(pockets == 1 || floor_tiles(&map) < floor);
+        }
+    }
+
+    #[test]
+    fn find_regions_picks_separate_rectangles_of_floor() {
+        let mut rng = ChaCha12Rng::seed_from_u64(0);
+
+        for seed in 0..20 {
+            let mut map = cave_map(seed);
+            KeepLargestArea.build(&mut map, &mut rng);
+            FindRegions {
+                max_regions: MAX_REGIONS,
+            }
+            .build(&mut map, &mut rng);
+
+            assert!(!map.rooms.is_empty() && map.rooms.len() as u32 <= MAX_REGIONS);
+            for (i, region) in map.rooms.iter().enumerate() {
+                for y in region.y1 + 1..=region.y2 {
+                    for x in region.x1 + 1..=region.x2 {
+                        assert_eq!(map.tiles[map.xy_idx(x, y)], TileType::Floor);
+                    }
+                }
+                assert!(map.rooms[..i].iter().all(|other| !other.intersect(region)));
+            }
+        }
+    }
+}
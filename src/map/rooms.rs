@@ -0,0 +1,65 @@
+use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+
+use super::{builder::MapBuilder, Map};
+use crate::rect::Rect;
+
+/// Scatters randomly sized rooms over the map using the algorithm from
+/// http://rogueliketutorials.com/tutorials/tcod/part-3/
+/// Rooms that would overlap one already dug are skipped. Nothing joins the rooms up, so this is
+/// followed by a `JoinRooms` pass.
+pub struct RandomRoomsBuilder {
+    // How many rooms are tried, some of which won't fit
+    pub max_rooms: i32,
+    pub min_size: i32,
+    pub max_size: i32,
+}
+
+impl MapBuilder for RandomRoomsBuilder {
+    fn build(&self, map: &mut Map, rng: &mut ChaCha12Rng) {
+        for _i in 0..self.max_rooms {
+            let w = rng.gen_range(self.min_size..self.max_size);
+            let h = rng.gen_range(self.min_size..self.max_size);
+            let x = rng.gen_range(1..=map.width - w - 1) - 1;
+            let y = rng.gen_range(1..=map.height - h - 1) - 1;
+            let new_room = Rect::new(x, y, w, h);
+            let mut ok = true;
+            for other_room in map.rooms.iter() {
+                if new_room.intersect(other_room) {
+                    ok = false
+                }
+            }
+            if ok {
+                map.apply_room_to_map(&new_room);
+                map.rooms.push(new_room);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::checks::edge_is_wall;
+
+    #[test]
+    fn rooms_never_overlap_or_reach_the_edge() {
+        for seed in 0..20 {
+            let mut map = Map::new(60, 40);
+            RandomRoomsBuilder {
+                max_rooms: 20,
+                min_size: 6,
+                max_size: 10,
+            }
+            .build(&mut map, &mut ChaCha12Rng::seed_from_u64(seed));
+
+            assert!(!map.rooms.is_empty());
+            assert!(edge_is_wall(&map));
+            for (i, room) in map.rooms.iter().enumerate() {
+                assert!(map.rooms[..i].iter().all(|other| !other.intersect(room)));
+            }
+        }
+    }
+}
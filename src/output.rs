@@ -0,0 +1,44 @@
+use std::str::FromStr;
+
+use serde::Serialize;
+
+// How results are printed by headless games and tournaments
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    Ron,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<OutputFormat, String> {
+        match format {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ron" => Ok(OutputFormat::Ron),
+            _ => Err(format!(
+                "Unknown output format {}, expected text, json or ron",
+                format
+            )),
+        }
+    }
+}
+
+// Prints results for other programs to read. Text output is written by hand by each caller.
+pub fn print_structured<T: Serialize>(format: OutputFormat, value: &T) {
+    let output = match format {
+        OutputFormat::Json => {
+            serde_json::to_string_pretty(value).map_err(|error| error.to_string())
+        }
+        OutputFormat::Ron => ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())
+            .map_err(|error| error.to_string()),
+        OutputFormat::Text => return,
+    };
+
+    match output {
+        Ok(output) => println!("{}", output),
+        Err(error) => eprintln!("Could not write the results: {}", error),
+    }
+}
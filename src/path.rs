@@ -1,49 +1,198 @@
-use bevy::prelude::{Changed, Commands, Entity, Query, Res, With, Without};
+use std::cmp::{min, Reverse};
+
+use bevy::prelude::{Changed, Commands, Entity, EventWriter, Query, Res, ResMut, With, Without};
 use pathfinding::prelude::{absdiff, astar};
 use rand::Rng;
-use rltk::BaseMap;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    combat::Dead, components::Name, destination::Destination, map::Map, position::Position,
+    combat::{Dead, Hp, Living},
+    components::Name,
+    creature::CreatureType,
+    destination::Destination,
+    flow::Flow,
+    initiative::TurnOrder,
+    map::{Dungeon, Level, Map, TileType},
+    position::Position,
+    scenario::Scenario,
 };
 
+// With diagonal movement, what a step costs before the ground is taken into account. A diagonal
+// step covers about 1.4 times the distance of a step along a row or column.
+const ORTHOGONAL_STEP: i32 = 5;
+const DIAGONAL_STEP: i32 = 7;
+
+// What stepping onto a tile another creature stands on adds to the cost of a path, so paths go
+// round other creatures when there's room to but still squeeze past them when there isn't
+const OCCUPIED_PATH_PENALTY: i32 = 4;
+
+// How many ticks a creature waits for someone to get out of its way before looking for a way
+// round them
+const REPATH_AFTER_WAITING: i32 = 2;
+
 pub struct Moves;
 
+/// How creatures step from tile to tile, picked by the scenario.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize)]
+pub enum Movement {
+    // Only along the rows and columns of the map
+    #[default]
+    Orthogonal,
+    // In all eight directions, but never across the corner of a wall
+    Diagonal,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Path {
     pub current: Vec<(i32, i32)>,
     pub index: usize,
     pub destination: Position,
+    // Ticks left before the creature gets off the slow ground it's on
+    #[serde(default)]
+    pub delay: i32,
+    // Ticks spent waiting for another creature to get off the next step
+    #[serde(default)]
+    pub waited: i32,
+    // Ticks the path has been out of date without being worked out again
+    #[serde(default)]
+    pub stale: i32,
+}
+
+impl Path {
+    /// The path of a creature following a flow field, which is given its next step every tick.
+    pub fn following(position: &Position) -> Path {
+        Path {
+            current: Vec::new(),
+            index: 0,
+            destination: position.clone(),
+            delay: 0,
+            waited: 0,
+            stale: 0,
+        }
+    }
+}
+
+// Sent whenever a door opens or closes, so creatures on that level look around again
+pub struct DoorEvent {
+    pub level: Level,
 }
 
 fn generate_path(
     map: &Map,
+    movement: Movement,
     position: &Position,
     destination: &Position,
+    avoid_creatures: bool,
 ) -> Option<(Vec<(i32, i32)>, i32)> {
-    let result = astar(
+    astar(
         &(position.0, position.1),
         |&(x, y)| {
-            vec![(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)]
-                .into_iter()
-                .filter(|&(x, y)| map.is_opaque(map.xy_idx(x, y)) == false)
-                .map(|p| (p, 1))
+            neighbours(map, movement, x, y, |x, y| {
+                step_cost(map, x, y, destination, avoid_creatures)
+            })
+        },
+        |&(x, y)| match movement {
+            Movement::Orthogonal => absdiff(x, destination.0) + absdiff(y, destination.1),
+            Movement::Diagonal => octile_distance(x, y, destination),
         },
-        |&(x, y)| absdiff(x, destination.0) + absdiff(y, destination.1),
         |&p| p.0 == destination.0 && p.1 == destination.1,
-    );
-    result
+    )
+}
+
+// What stepping onto a tile costs. Other creatures can be treated as soft obstacles, except on
+// the destination, which is often the creature being chased.
+fn step_cost(map: &Map, x: i32, y: i32, destination: &Position, avoid_creatures: bool) -> i32 {
+    let idx = map.xy_idx(x, y);
+    let occupied = avoid_creatures
+        && map.occupant(idx).is_some()
+        && (x != destination.0 || y != destination.1);
+
+    if occupied {
+        map.path_cost(idx) + OCCUPIED_PATH_PENALTY
+    } else {
+        map.path_cost(idx)
+    }
+}
+
+/// Every walkable tile a creature can step onto from (x, y), with what the step costs. `cost`
+/// gives the cost of the ground stepped onto; with diagonal movement that is scaled by the length
+/// of the step. A diagonal step is only allowed when both tiles beside it are walkable too, so
+/// creatures never squeeze past the corner of a wall.
+pub fn neighbours(
+    map: &Map,
+    movement: Movement,
+    x: i32,
+    y: i32,
+    cost: impl Fn(i32, i32) -> i32,
+) -> Vec<((i32, i32), i32)> {
+    let walkable = |x: i32, y: i32| map.is_walkable(map.xy_idx(x, y));
+
+    if movement == Movement::Orthogonal {
+        return vec![(x + 1, y), (x - 1, y), (x, y + 1), (x, y - 1)]
+            .into_iter()
+            .filter(|&(x, y)| walkable(x, y))
+            .map(|(x, y)| ((x, y), cost(x, y)))
+            .collect();
+    }
+
+    let mut steps = Vec::new();
+
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            if (dx == 0 && dy == 0) || !walkable(x + dx, y + dy) {
+                continue;
+            }
+
+            let step = if dx != 0 && dy != 0 {
+                if !walkable(x + dx, y) || !walkable(x, y + dy) {
+                    continue;
+                }
+                DIAGONAL_STEP
+            } else {
+                ORTHOGONAL_STEP
+            };
+
+            steps.push(((x + dx, y + dy), step * cost(x + dx, y + dy)));
+        }
+    }
+
+    steps
 }
 
+// The cost of the shortest route over open floor, going diagonally as far as it helps and then
+// straight
+fn octile_distance(x: i32, y: i32, destination: &Position) -> i32 {
+    let dx = absdiff(x, destination.0);
+    let dy = absdiff(y, destination.1);
+    ORTHOGONAL_STEP * (dx + dy) + (DIAGONAL_STEP - 2 * ORTHOGONAL_STEP) * min(dx, dy)
+}
+
+// A creature that has just been given somewhere to go
+type Heading<'a> = (Entity, &'a Name, &'a Position, &'a Level, &'a Destination);
+type NewDestination = (With<Moves>, Changed<Destination>, Without<Path>);
+
 pub fn path_to_destination(
     mut commands: Commands,
-    query: Query<(Entity, &Name, &Position, &Destination), (With<Moves>, Changed<Destination>)>,
-    map: Res<Map>,
+    // Creatures restored from a save arrive with their path already set
+    query: Query<Heading, NewDestination>,
+    dungeon: Res<Dungeon>,
+    scenario: Res<Scenario>,
+    mut rng: ResMut<ChaCha12Rng>,
     // mut log: ResMut<Vec<String>>,
 ) {
-    let mut rng = rand::thread_rng();
+    // In the order creatures were spawned in, which a game loaded from a save keeps
+    let mut creatures: Vec<_> = query.iter().collect();
+    creatures.sort_by_key(|(entity, ..)| *entity);
 
-    for (entity, _name, position, destination) in query.iter() {
-        let result = generate_path(&map, &position, &destination.position);
+    for (entity, _name, position, level, destination) in creatures {
+        let result = generate_path(
+            dungeon.map(level),
+            scenario.movement,
+            position,
+            &destination.position,
+            true,
+        );
 
         if let Some(result) = result {
             let last_position = result.0.last().unwrap();
@@ -52,6 +201,9 @@ pub fn path_to_destination(
                 current: result.0,
                 index: rng.gen_range(0..=1),
                 destination,
+                delay: 0,
+                waited: 0,
+                stale: 0,
             });
 
             // [EXTRA DEBUG]
@@ -60,21 +212,599 @@ pub fn path_to_destination(
     }
 }
 
+// Query filter for creatures walking a path of their own rather than following a flow field
+type OwnPath = (With<Moves>, Without<Flow>, Without<Dead>);
+
+// A path goes out of date when the creature it leads to has moved off the end of it, when the
+// ground ahead can no longer be walked on, or when the creature has waited a while for someone to
+// get out of its way, unless that someone is standing on its destination. Only as many paths as
+// the scenario's repath budget allows are worked out again each tick, those out of date longest
+// first, and the rest are followed as they are in the meantime. A creature with no way left to
+// its destination gives up on it.
+pub fn repath(
+    mut commands: Commands,
+    mut creature_query: Query<(&Position, &Level, &mut Destination, &mut Path), OwnPath>,
+    target_query: Query<(&Position, &Level), Living>,
+    turn_order: Res<TurnOrder>,
+    dungeon: Res<Dungeon>,
+    scenario: Res<Scenario>,
+) {
+    let mut stale: Vec<(Entity, i32)> = Vec::new();
+
+    for &entity in turn_order.0.iter() {
+        let (_, level, mut destination, mut path) = match creature_query.get_mut(entity) {
+            Ok(creature) => creature,
+            Err(_) => continue,
+        };
+
+        // Chasers follow their target wherever it goes on the level
+        if let Some(target) = destination.target {
+            match target_query.get(target) {
+                Ok((target_position, target_level)) if target_level == level => {
+                    if destination.position != *target_position {
+                        destination.position = target_position.clone();
+                    }
+                }
+                _ => destination.target = None,
+            }
+        }
+
+        let map = dungeon.map(level);
+        let ahead = path.current.iter().skip(path.index);
+        let blocked = ahead
+            .clone()
+            .any(|&(x, y)| !map.is_walkable(map.xy_idx(x, y)));
+        let waited_out = path.waited >= REPATH_AFTER_WAITING && ahead.count() > 1;
+
+        if destination.position != path.destination || blocked || waited_out {
+            path.stale += 1;
+            stale.push((entity, path.stale));
+        }
+    }
+
+    // Sorting is stable, so among paths out of date as long as each other the quickest go first
+    stale.sort_by_key(|&(_, ticks)| Reverse(ticks));
+
+    for &(entity, _) in stale.iter().take(scenario.repath_budget) {
+        let (position, level, destination, mut path) = creature_query.get_mut(entity).unwrap();
+        let result = generate_path(
+            dungeon.map(level),
+            scenario.movement,
+            position,
+            &destination.position,
+            true,
+        );
+
+        match result {
+            Some((current, _)) => {
+                // The first tile of the new path is the one the creature is already on
+                path.current = current;
+                path.index = 1;
+                path.destination = destination.position.clone();
+                path.waited = 0;
+                path.stale = 0;
+            }
+            None => {
+                commands
+                    .entity(entity)
+                    .remove::<Path>()
+                    .remove::<Destination>();
+            }
+        }
+    }
+}
+
+// Living creatures block the tile they stand on. Worked out afresh at the start of every tick,
+// then kept up to date as creatures move during it.
+pub fn update_occupancy(
+    creature_query: Query<(Entity, &Position, &Level), Living>,
+    mut dungeon: ResMut<Dungeon>,
+) {
+    for map in dungeon.levels.iter_mut() {
+        map.occupants = vec![None; map.tiles.len()];
+    }
+
+    for (entity, position, level) in creature_query.iter() {
+        let map = dungeon.map_mut(level);
+        let idx = map.xy_idx(position.0, position.1);
+        map.occupants[idx] = Some(entity);
+    }
+}
+
+// A creature taking the next step of its path
+type Walker<'a> = (
+    &'a mut Position,
+    &'a Level,
+    &'a mut Path,
+    &'a CreatureType,
+    Option<&'a Flow>,
+);
+
+// Creatures move in turn order, so the quickest get first claim on a tile. A creature whose next
+// step is taken waits, or trades places with the creature in the way if that one is on its side
+// and heading onto its tile.
 pub fn move_path(
     mut commands: Commands,
-    mut creature_query: Query<(Entity, &mut Position, &mut Path), (With<Moves>, Without<Dead>)>,
+    mut creature_query: Query<Walker, (With<Moves>, Without<Dead>)>,
+    turn_order: Res<TurnOrder>,
+    scenario: Res<Scenario>,
+    mut dungeon: ResMut<Dungeon>,
+    mut rng: ResMut<ChaCha12Rng>,
+    mut door_event: EventWriter<DoorEvent>,
 ) {
-    for (entity, mut position, mut path) in creature_query.iter_mut() {
-        if path.current.len() > path.index {
-            let (next_x, next_y) = path.current[path.index];
-            position.0 = next_x;
-            position.1 = next_y;
-            path.index += 1;
-        } else {
-            commands
-                .entity(entity)
-                .remove::<Path>()
-                .remove::<Destination>();
+    let mut moved: Vec<Entity> = Vec::new();
+
+    for &entity in turn_order.0.iter() {
+        if moved.contains(&entity) {
+            continue;
         }
+        moved.push(entity);
+
+        let (level, creature_type, from, next) = match creature_query.get_mut(entity) {
+            Ok((position, level, mut path, creature_type, flow)) => {
+                if path.delay > 0 {
+                    path.delay -= 1;
+                    continue;
+                }
+
+                // Creatures following a flow field take whichever step leads downhill from where
+                // they are now, and stop once there's nowhere lower to go
+                if let Some(flow) = flow {
+                    let map = dungeon.map(level);
+                    let downhill = map.flow.get(*flow, creature_type).and_then(|field| {
+                        field.downhill(map, scenario.movement, position.0, position.1, &mut rng)
+                    });
+
+                    match downhill {
+                        Some(next) => {
+                            path.current = vec![next];
+                            path.index = 0;
+                        }
+                        None => {
+                            commands.entity(entity).remove::<Flow>().remove::<Path>();
+                            continue;
+                        }
+                    }
+                }
+
+                if path.current.len() <= path.index {
+                    commands
+                        .entity(entity)
+                        .remove::<Path>()
+                        .remove::<Destination>();
+                    continue;
+                }
+
+                let next = path.current[path.index];
+                (
+                    *level,
+                    creature_type.clone(),
+                    (position.0, position.1),
+                    next,
+                )
+            }
+            Err(_) => continue,
+        };
+
+        let map = dungeon.map(&level);
+        let (from_idx, next_idx) = (map.xy_idx(from.0, from.1), map.xy_idx(next.0, next.1));
+
+        let blocker = map.occupant(next_idx).filter(|&other| other != entity);
+        if let Some(other) = blocker {
+            let swaps = !moved.contains(&other)
+                && match creature_query.get_mut(other) {
+                    Ok((_, other_level, other_path, other_type, _)) => {
+                        *other_level == level
+                            && *other_type == creature_type
+                            && other_path.delay == 0
+                            && other_path.current.get(other_path.index) == Some(&from)
+                    }
+                    Err(_) => false,
+                };
+
+            if !swaps {
+                let (_, _, mut path, ..) = creature_query.get_mut(entity).unwrap();
+                path.waited += 1;
+                continue;
+            }
+
+            moved.push(other);
+            let (mut position, _, mut path, ..) = creature_query.get_mut(other).unwrap();
+            let map = dungeon.map_mut(&level);
+            step(&mut position, &mut path, map, &level, &mut door_event, from);
+            map.move_occupant(other, next_idx, from_idx);
+        }
+
+        let (mut position, _, mut path, ..) = creature_query.get_mut(entity).unwrap();
+        let map = dungeon.map_mut(&level);
+        step(&mut position, &mut path, map, &level, &mut door_event, next);
+        map.move_occupant(entity, from_idx, next_idx);
+    }
+}
+
+// Moves a creature onto the next tile of its path, opening the door there if it's shut
+fn step(
+    position: &mut Position,
+    path: &mut Path,
+    map: &mut Map,
+    level: &Level,
+    door_event: &mut EventWriter<DoorEvent>,
+    (x, y): (i32, i32),
+) {
+    let idx = map.xy_idx(x, y);
+    if map.tiles[idx] == (TileType::Door { open: false }) {
+        map.tiles[idx] = TileType::Door { open: true };
+        door_event.send(DoorEvent { level: *level });
+    }
+
+    position.0 = x;
+    position.1 = y;
+    path.index += 1;
+    path.delay = map.tiles[idx].move_time() - 1;
+    path.waited = 0;
+}
+
+// Doors swing shut once nobody is standing in them, living or dead
+pub fn close_doors(
+    creature_query: Query<(&Position, &Level), With<Hp>>,
+    mut dungeon: ResMut<Dungeon>,
+    mut door_event: EventWriter<DoorEvent>,
+) {
+    let open_doors: Vec<(Level, usize)> = dungeon
+        .levels
+        .iter()
+        .enumerate()
+        .flat_map(|(level, map)| {
+            (0..map.tiles.len())
+                .filter(move |idx| map.tiles[*idx] == (TileType::Door { open: true }))
+                .map(move |idx| (Level(level), idx))
+        })
+        .collect();
+
+    for (level, idx) in open_doors {
+        let map = dungeon.map(&level);
+        let occupied = creature_query.iter().any(|(position, creature_level)| {
+            *creature_level == level && map.xy_idx(position.0, position.1) == idx
+        });
+
+        if !occupied {
+            dungeon.map_mut(&level).tiles[idx] = TileType::Door { open: false };
+            door_event.send(DoorEvent { level });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        app::Events,
+        prelude::{IntoSystem, Stage, SystemStage, World},
+    };
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::char_to_tile;
+
+    // A map drawn with the same glyphs it's shown with
+    fn map_from(rows: &[&str]) -> Map {
+        let mut map = Map::new(rows[0].len() as i32, rows.len() as i32);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, glyph) in row.chars().enumerate() {
+                let idx = map.xy_idx(x as i32, y as i32);
+                map.tiles[idx] = char_to_tile(glyph).unwrap();
+            }
+        }
+        map
+    }
+
+    fn tile_at(world: &World, x: i32, y: i32) -> TileType {
+        let map = world.get_resource::<Dungeon>().unwrap().map(&Level(0));
+        map.tiles[map.xy_idx(x, y)]
+    }
+
+    #[test]
+    fn paths_go_through_doors_but_not_walls() {
+        let door = map_from(&["#######", "#..+..#", "#######"]);
+        let (steps, _) = generate_path(
+            &door,
+            Movement::Orthogonal,
+            &Position(1, 1),
+            &Position(5, 1),
+            false,
+        )
+        .unwrap();
+        assert!(steps.contains(&(3, 1)));
+
+        let wall = map_from(&["#######", "#..#..#", "#######"]);
+        assert!(generate_path(
+            &wall,
+            Movement::Orthogonal,
+            &Position(1, 1),
+            &Position(5, 1),
+            false
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn paths_go_round_slow_and_dangerous_ground() {
+        for (ground, glyph) in [("water", '~'), ("rubble", ':'), ("lava", '^')].iter() {
+            // Three tiles of it straight ahead, or two extra steps round it
+            let middle = format!("#.{0}{0}{0}.#", glyph);
+            let map = map_from(&["#######", &middle, "#.....#", "#######"]);
+
+            let (steps, _) = generate_path(
+                &map,
+                Movement::Orthogonal,
+                &Position(1, 1),
+                &Position(5, 1),
+                false,
+            )
+            .unwrap();
+            assert!(!steps.contains(&(3, 1)), "{}", ground);
+        }
+
+        // With no way round, lava is crossed after all
+        let map = map_from(&["#######", "#..^..#", "#######"]);
+        assert!(generate_path(
+            &map,
+            Movement::Orthogonal,
+            &Position(1, 1),
+            &Position(5, 1),
+            false
+        )
+        .is_some());
+    }
+
+    #[test]
+    fn diagonal_movement_cuts_across_open_floor() {
+        let map = map_from(&["######", "#....#", "#....#", "#....#", "######"]);
+        let (start, end) = (Position(1, 1), Position(4, 3));
+
+        let (steps, _) = generate_path(&map, Movement::Orthogonal, &start, &end, false).unwrap();
+        assert_eq!(steps.len(), 6);
+
+        let (steps, _) = generate_path(&map, Movement::Diagonal, &start, &end, false).unwrap();
+        assert_eq!(steps.len(), 4);
+        assert_eq!(steps.last(), Some(&(4, 3)));
+    }
+
+    #[test]
+    fn diagonal_steps_never_cut_the_corner_of_a_wall() {
+        let map = map_from(&["####", "#..#", "##.#", "####"]);
+
+        let (steps, _) = generate_path(
+            &map,
+            Movement::Diagonal,
+            &Position(1, 1),
+            &Position(2, 2),
+            false,
+        )
+        .unwrap();
+        assert_eq!(steps, vec![(1, 1), (2, 1), (2, 2)]);
+    }
+
+    // A dungeon of one level drawn from the rows, ready for creatures to walk around it
+    fn world_from(rows: &[&str]) -> World {
+        let mut world = World::default();
+        world.insert_resource(Dungeon {
+            levels: vec![map_from(rows)],
+        });
+        world.insert_resource(TurnOrder::default());
+        world.insert_resource(Events::<DoorEvent>::default());
+        world.insert_resource(default_scenario());
+        world.insert_resource(ChaCha12Rng::seed_from_u64(0));
+        world
+    }
+
+    fn default_scenario() -> Scenario {
+        ron::de::from_str(include_str!("../assets/scenarios/default.ron")).unwrap()
+    }
+
+    // A creature partway along a path, or standing still if it has no steps left to take
+    fn walker(world: &mut World, position: Position, steps: &[(i32, i32)]) -> Entity {
+        let creature = world
+            .spawn()
+            .insert_bundle((
+                Moves,
+                Hp(10),
+                CreatureType("human".to_string()),
+                position.clone(),
+                Level(0),
+            ))
+            .id();
+        if let Some(&(x, y)) = steps.last() {
+            world.entity_mut(creature).insert_bundle((
+                Destination {
+                    position: Position(x, y),
+                    target: None,
+                },
+                Path {
+                    current: steps.to_vec(),
+                    index: 0,
+                    destination: Position(x, y),
+                    delay: 0,
+                    waited: 0,
+                    stale: 0,
+                },
+            ));
+        }
+
+        world
+            .get_resource_mut::<TurnOrder>()
+            .unwrap()
+            .0
+            .push(creature);
+        creature
+    }
+
+    // The parts of a tick that keep creatures' paths up to date and move them along them
+    fn tick(world: &mut World) {
+        SystemStage::single_threaded()
+            .with_system(update_occupancy.system())
+            .run(world);
+        SystemStage::single_threaded()
+            .with_system(repath.system())
+            .run(world);
+        SystemStage::single_threaded()
+            .with_system(move_path.system())
+            .run(world);
+        SystemStage::single_threaded()
+            .with_system(close_doors.system())
+            .run(world);
+    }
+
+    fn position(world: &World, creature: Entity) -> Position {
+        world.get::<Position>(creature).unwrap().clone()
+    }
+
+    #[test]
+    fn doors_open_for_creatures_and_close_behind_them() {
+        let mut world = world_from(&["#######", "#..+..#", "#######"]);
+        walker(&mut world, Position(2, 1), &[(3, 1), (4, 1)]);
+
+        // Standing in the doorway holds the door open
+        tick(&mut world);
+        assert_eq!(tile_at(&world, 3, 1), TileType::Door { open: true });
+
+        tick(&mut world);
+        assert_eq!(tile_at(&world, 3, 1), TileType::Door { open: false });
+    }
+
+    #[test]
+    fn slow_ground_takes_an_extra_tick_to_leave() {
+        let mut world = world_from(&["#####", "#.~.#", "#####"]);
+        let creature = walker(&mut world, Position(1, 1), &[(2, 1), (3, 1)]);
+
+        let mut positions = Vec::new();
+        for _ in 0..3 {
+            tick(&mut world);
+            positions.push(position(&world, creature));
+        }
+
+        assert_eq!(positions, [Position(2, 1), Position(2, 1), Position(3, 1)]);
+    }
+
+    #[test]
+    fn creatures_wait_rather_than_share_a_tile() {
+        let mut world = world_from(&["######", "#....#", "######"]);
+        let mover = walker(&mut world, Position(1, 1), &[(2, 1), (3, 1)]);
+        walker(&mut world, Position(3, 1), &[]);
+
+        for _ in 0..3 {
+            tick(&mut world);
+        }
+
+        // It's the last step, so there's no point looking for a way round
+        assert_eq!(position(&world, mover), Position(2, 1));
+        assert_eq!(world.get::<Path>(mover).unwrap().waited, 2);
+    }
+
+    #[test]
+    fn creatures_heading_onto_each_others_tiles_swap_places() {
+        let mut world = world_from(&["#####", "#...#", "#####"]);
+        let left = walker(&mut world, Position(1, 1), &[(2, 1)]);
+        let right = walker(&mut world, Position(2, 1), &[(1, 1)]);
+
+        tick(&mut world);
+
+        assert_eq!(position(&world, left), Position(2, 1));
+        assert_eq!(position(&world, right), Position(1, 1));
+        let map = world.get_resource::<Dungeon>().unwrap().map(&Level(0));
+        assert_eq!(map.occupant(map.xy_idx(2, 1)), Some(left));
+        assert_eq!(map.occupant(map.xy_idx(1, 1)), Some(right));
+    }
+
+    #[test]
+    fn creatures_kept_waiting_look_for_a_way_round() {
+        let mut world = world_from(&["#######", "#.....#", "#.....#", "#######"]);
+        let mover = walker(&mut world, Position(1, 1), &[(2, 1), (3, 1), (4, 1)]);
+        walker(&mut world, Position(2, 1), &[]);
+
+        tick(&mut world);
+        tick(&mut world);
+        assert_eq!(position(&world, mover), Position(1, 1));
+
+        tick(&mut world);
+        assert_eq!(position(&world, mover), Position(1, 2));
+        assert_eq!(
+            world.get::<Path>(mover).unwrap().destination,
+            Position(4, 1)
+        );
+    }
+
+    #[test]
+    fn only_the_repath_budget_is_spent_each_tick() {
+        let mut world = world_from(&["#######", "#.....#", "#.....#", "#.....#", "#######"]);
+        world.get_resource_mut::<Scenario>().unwrap().repath_budget = 1;
+        let creatures: Vec<Entity> = (1..4)
+            .map(|y| walker(&mut world, Position(1, y), &[(1, y), (2, y)]))
+            .collect();
+        // Every destination has moved on since the paths were worked out
+        for creature in creatures.iter() {
+            world.get_mut::<Destination>(*creature).unwrap().position.0 = 5;
+        }
+
+        let mut repathing = SystemStage::single_threaded().with_system(repath.system());
+        let up_to_date = |world: &World| {
+            creatures
+                .iter()
+                .filter(|creature| world.get::<Path>(**creature).unwrap().stale == 0)
+                .count()
+        };
+
+        repathing.run(&mut world);
+        assert_eq!(up_to_date(&world), 1);
+        repathing.run(&mut world);
+        assert_eq!(up_to_date(&world), 2);
+        repathing.run(&mut world);
+        assert_eq!(up_to_date(&world), 3);
+    }
+
+    #[test]
+    fn chasers_follow_their_target_until_it_dies() {
+        let mut world = world_from(&["#######", "#.....#", "#######"]);
+        let chaser = walker(&mut world, Position(1, 1), &[(1, 1), (2, 1)]);
+        let target = walker(&mut world, Position(4, 1), &[]);
+        world.get_mut::<Destination>(chaser).unwrap().target = Some(target);
+
+        let mut repathing = SystemStage::single_threaded().with_system(repath.system());
+        repathing.run(&mut world);
+        assert_eq!(
+            world.get::<Path>(chaser).unwrap().destination,
+            Position(4, 1)
+        );
+
+        world.get_mut::<Position>(target).unwrap().0 = 5;
+        repathing.run(&mut world);
+        assert_eq!(
+            world.get::<Path>(chaser).unwrap().destination,
+            Position(5, 1)
+        );
+
+        world.entity_mut(target).insert(Dead);
+        repathing.run(&mut world);
+        assert_eq!(world.get::<Destination>(chaser).unwrap().target, None);
+        assert_eq!(
+            world.get::<Destination>(chaser).unwrap().position,
+            Position(5, 1)
+        );
+    }
+
+    #[test]
+    fn paths_go_round_other_creatures_when_there_is_room() {
+        let mut map = map_from(&["#######", "#.....#", "#.....#", "#######"]);
+        map.occupants = vec![None; map.tiles.len()];
+        let idx = map.xy_idx(3, 1);
+        map.occupants[idx] = Some(Entity::new(0));
+        let (start, end) = (Position(1, 1), Position(5, 1));
+
+        let (steps, _) = generate_path(&map, Movement::Orthogonal, &start, &end, true).unwrap();
+        assert!(!steps.contains(&(3, 1)));
+
+        // Unless the creature is standing on the destination
+        let (steps, _) =
+            generate_path(&map, Movement::Orthogonal, &start, &Position(3, 1), true).unwrap();
+        assert_eq!(steps.len(), 3);
     }
 }
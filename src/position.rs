@@ -1,11 +1,17 @@
 use std::cmp::{max, min};
 
-use bevy::prelude::{Commands, Entity, Query, Res, Without};
+use bevy::prelude::{Commands, Entity, Query, ResMut, Without};
 use rand::prelude::SliceRandom;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
 
-use crate::map::Map;
+use crate::{
+    combat::Hp,
+    map::{Dungeon, Level},
+    rect::Rect,
+};
 
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub struct Position(pub i32, pub i32);
 
 /// Calculates a Pythagoras distance between two points, and skips the square root for speed.
@@ -15,21 +21,90 @@ pub fn distance2d_pythagoras_squared(start: &Position, end: &Position) -> f32 {
     (dx * dx) + (dy * dy)
 }
 
+// Creatures start on a free tile of ground they can stand on, so a zone with none left is passed
+// over. A creature with nowhere free to start waits until somewhere is. Items can go anywhere
+// creatures can start. Runs once before the first tick, so creatures are in place for it, and
+// then every tick for any still waiting. Everything is placed in the order it was spawned in, so a
+// game loaded from a save places them the same way the saved one would have.
 pub fn assign_positions(
     mut commands: Commands,
-    creature_query: Query<Entity, Without<Position>>,
-    map: Res<Map>,
+    creature_query: Query<(Entity, &Level, Option<&Hp>), Without<Position>>,
+    mut dungeon: ResMut<Dungeon>,
+    mut rng: ResMut<ChaCha12Rng>,
 ) {
-    let mut rng = rand::thread_rng();
+    let mut unplaced: Vec<(Entity, &Level, Option<&Hp>)> = creature_query.iter().collect();
+    unplaced.sort_by_key(|(entity, ..)| *entity);
 
-    for entity in creature_query.iter() {
-        let room_option = map.rooms.choose(&mut rng);
+    for (entity, level, hp) in unplaced {
+        let map = dungeon.map(level);
+        let blocks = hp.is_some();
+        let free = |x: i32, y: i32| {
+            let idx = map.xy_idx(x, y);
+            map.tiles[idx].can_start_on() && !(blocks && map.occupant(idx).is_some())
+        };
+
+        let open_zones: Vec<&Rect> = map
+            .start_zones()
+            .iter()
+            .filter(|zone| {
+                (zone.x1 + 1..=zone.x2).any(|x| (zone.y1 + 1..=zone.y2).any(|y| free(x, y)))
+            })
+            .collect();
+
+        let room_option = open_zones.choose(&mut *rng);
         if let Some(room) = room_option {
-            let room_centre = room.random();
+            let mut spot = room.random(&mut rng);
+            while !free(spot.0, spot.1) {
+                spot = room.random(&mut rng);
+            }
 
-            commands
-                .entity(entity)
-                .insert(Position(room_centre.0, room_centre.1));
+            if blocks {
+                let map = dungeon.map_mut(level);
+                let idx = map.xy_idx(spot.0, spot.1);
+                map.occupants[idx] = Some(entity);
+            }
+            commands.entity(entity).insert(Position(spot.0, spot.1));
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::{IntoSystem, Stage, SystemStage, World};
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::{Map, TileType};
+
+    #[test]
+    fn creatures_never_start_on_the_same_tile() {
+        // A room with only two tiles of floor in it
+        let mut map = Map::new(4, 3);
+        for x in 1..3 {
+            let idx = map.xy_idx(x, 1);
+            map.tiles[idx] = TileType::Floor;
+        }
+        map.rooms.push(Rect::new(0, 0, 2, 1));
+        map.occupants = vec![None; map.tiles.len()];
+
+        let mut world = World::default();
+        world.insert_resource(Dungeon { levels: vec![map] });
+        world.insert_resource(ChaCha12Rng::seed_from_u64(0));
+        let creatures: Vec<Entity> = (0..3)
+            .map(|_| world.spawn().insert_bundle((Hp(10), Level(0))).id())
+            .collect();
+
+        SystemStage::single_threaded()
+            .with_system(assign_positions.system())
+            .run(&mut world);
+
+        let mut spots: Vec<Position> = creatures
+            .iter()
+            .filter_map(|creature| world.get::<Position>(*creature).cloned())
+            .collect();
+        spots.sort();
+        assert_eq!(spots, vec![Position(1, 1), Position(2, 1)]);
+        // The last one waits for somewhere to come free
+        assert!(world.get::<Position>(creatures[2]).is_none());
+    }
+}
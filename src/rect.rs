@@ -1,7 +1,10 @@
 use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
 
 use crate::position::Position;
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Rect {
     pub x1: i32,
     pub x2: i32,
@@ -28,12 +31,10 @@ impl Rect {
         Position((self.x1 + self.x2) / 2, (self.y1 + self.y2) / 2)
     }
 
-    pub fn random(&self) -> Position {
-        let mut rng = rand::thread_rng();
-
+    pub fn random(&self, rng: &mut ChaCha12Rng) -> Position {
         Position(
-            rng.gen_range(self.x1+1..=self.x2),
-            rng.gen_range(self.y1+1..=self.y2),
+            rng.gen_range(self.x1 + 1..=self.x2),
+            rng.gen_range(self.y1 + 1..=self.y2),
         )
     }
 }
@@ -1,20 +1,90 @@
 use std::{convert::TryInto, io::stdout};
 
-use bevy::prelude::Query;
+use bevy::prelude::{Query, Res, ResMut};
 use crossterm::{cursor, style, QueueableCommand};
+use serde::{ser::Error, Deserialize, Serialize, Serializer};
 
-use crate::position::Position;
+use crate::{
+    combat::Living,
+    map::{Dungeon, Level},
+    position::Position,
+    spawner::Tracked,
+};
 
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Render {
+    #[serde(serialize_with = "serialize_colour")]
     pub colour: style::Color,
     pub char: String,
 }
 
+// Crossterm reads colours back from names like "dark_cyan" but writes them out as enum variants,
+// so colours are written as names here to keep saves and data files loadable
+pub fn serialize_colour<S: Serializer>(
+    colour: &style::Color,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    use style::Color;
+
+    let name = match colour {
+        Color::Black => "black",
+        Color::DarkGrey => "dark_grey",
+        Color::Red => "red",
+        Color::DarkRed => "dark_red",
+        Color::Green => "green",
+        Color::DarkGreen => "dark_green",
+        Color::Yellow => "yellow",
+        Color::DarkYellow => "dark_yellow",
+        Color::Blue => "blue",
+        Color::DarkBlue => "dark_blue",
+        Color::Magenta => "magenta",
+        Color::DarkMagenta => "dark_magenta",
+        Color::Cyan => "cyan",
+        Color::DarkCyan => "dark_cyan",
+        Color::White => "white",
+        Color::Grey => "grey",
+        Color::Rgb { r, g, b } => return [r, g, b].serialize(serializer),
+        Color::AnsiValue(value) => return value.serialize(serializer),
+        Color::Reset => return Err(S::Error::custom("the reset colour can't be saved")),
+    };
+
+    serializer.serialize_str(name)
+}
+
+// The level of the dungeon drawn in the terminal
+#[derive(Default)]
+pub struct ShownLevel(pub Level);
+
+// Follows the tracked creature from level to level. Without one alive, the level with the most
+// creatures left alive is shown, or the highest of them when there's a tie.
+pub fn pick_shown_level(
+    creature_query: Query<(&Level, Option<&Tracked>), Living>,
+    dungeon: Res<Dungeon>,
+    mut shown_level: ResMut<ShownLevel>,
+) {
+    let mut counts = vec![0; dungeon.levels.len()];
+    for (level, tracked) in creature_query.iter() {
+        if tracked.is_some() {
+            shown_level.0 = *level;
+            return;
+        }
+        counts[level.0] += 1;
+    }
+
+    if let Some(busiest) = (0..counts.len()).rev().max_by_key(|level| counts[*level]) {
+        shown_level.0 = Level(busiest);
+    }
+}
+
 // This system updates the score for each entity with the "Player" and "Score" component.
-pub fn draw_entities(query: Query<(&Position, &Render)>) {
+pub fn draw_entities(query: Query<(&Position, &Level, &Render)>, shown_level: Res<ShownLevel>) {
     let mut stdout = stdout();
 
-    for (position, render) in query.iter() {
+    for (position, level, render) in query.iter() {
+        if *level != shown_level.0 {
+            continue;
+        }
+
         stdout
             .queue(cursor::MoveTo(
                 position.0.try_into().unwrap(),
@@ -0,0 +1,378 @@
+use std::{
+    cmp::{max, min},
+    convert::TryInto,
+    fs,
+    io::{stdout, Write},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    app::{AppExit, Events, ManualEventReader},
+    ecs::system::SystemParam,
+    prelude::{App, EventReader, Query, Res, ResMut, With},
+};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyModifiers},
+    style::{self, Color},
+    terminal, QueueableCommand,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    combat::{AttackEvent, DeathEvent, Hp},
+    components::Name,
+    creature::CreatureType,
+    equipment::ItemEvent,
+    log::{describe_attack, describe_burn, describe_death, describe_item, describe_stairs},
+    map::{Dungeon, Level},
+    position::Position,
+    scenario::Scenario,
+    stairs::StairsEvent,
+    terrain::BurnEvent,
+    EndGameEvent, TickCount,
+};
+
+// Bump this whenever the layout of `Replay` changes
+pub const REPLAY_VERSION: u32 = 3;
+
+const MIN_TICK_DELAY: Duration = Duration::from_millis(10);
+const MAX_TICK_DELAY: Duration = Duration::from_millis(5000);
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpawnRecord {
+    pub name: String,
+    pub creature_type: Option<CreatureType>,
+    pub position: Option<Position>,
+    pub level: Option<Level>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct CreatureRecord {
+    pub name: String,
+    pub position: Option<Position>,
+    pub level: Option<Level>,
+    pub hp: i32,
+}
+
+// Everything that happened during one tick, and where every creature ended up
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct TickRecord {
+    pub tick: i32,
+    pub events: Vec<String>,
+    pub creatures: Vec<CreatureRecord>,
+}
+
+/// A recorded battle. The seed and scenario are enough to play it back; the spawn list and tick
+/// records are kept so playback can tell if the game no longer plays out the way it did when
+/// recorded.
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    // The name of the scenario the battle was recorded with
+    pub scenario: String,
+    pub spawns: Vec<SpawnRecord>,
+    pub ticks: Vec<TickRecord>,
+}
+
+// A replay being recorded, written out to `path` when the game ends
+pub struct ReplayRecorder {
+    pub path: PathBuf,
+    pub replay: Replay,
+}
+
+// A replay being played back, checked tick by tick against the game as it plays out
+pub struct ReplayPlayback {
+    pub replay: Replay,
+    pub diverged_at: Option<i32>,
+}
+
+pub fn load_replay(path: &PathBuf) -> Result<Replay, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+
+    let replay: Replay = ron::de::from_str(&contents)
+        .map_err(|error| format!("Could not parse {}: {}", path.display(), error))?;
+
+    if replay.version != REPLAY_VERSION {
+        return Err(format!(
+            "{} is a version {} replay, but this game reads version {}",
+            path.display(),
+            replay.version,
+            REPLAY_VERSION
+        ));
+    }
+
+    Ok(replay)
+}
+
+// What's recorded about everything spawned at the start, and about every creature each tick
+type SpawnComponents<'a> = (
+    &'a Name,
+    Option<&'a CreatureType>,
+    Option<&'a Position>,
+    Option<&'a Level>,
+);
+type CreatureComponents<'a> = (&'a Name, Option<&'a Position>, Option<&'a Level>, &'a Hp);
+
+// Every event that goes into a tick's record
+#[derive(SystemParam)]
+pub struct TickEvents<'a> {
+    attack_event: EventReader<'a, AttackEvent>,
+    item_event: EventReader<'a, ItemEvent>,
+    stairs_event: EventReader<'a, StairsEvent>,
+    burn_event: EventReader<'a, BurnEvent>,
+    death_event: EventReader<'a, DeathEvent>,
+    end_game_event: EventReader<'a, EndGameEvent>,
+}
+
+// Returns the record for the current tick, and whether the game ended on it
+fn current_tick(
+    tick_count: &TickCount,
+    tick_events: &mut TickEvents,
+    creature_query: &Query<CreatureComponents, With<CreatureType>>,
+) -> (TickRecord, bool) {
+    let mut events: Vec<String> = Vec::new();
+
+    for event in tick_events.attack_event.iter() {
+        events.extend(describe_attack(event));
+    }
+    for event in tick_events.item_event.iter() {
+        events.push(describe_item(event));
+    }
+    for event in tick_events.stairs_event.iter() {
+        events.push(describe_stairs(event));
+    }
+    for event in tick_events.burn_event.iter() {
+        events.push(describe_burn(event));
+    }
+    for event in tick_events.death_event.iter() {
+        events.push(describe_death(event));
+    }
+
+    let mut game_over = false;
+    for event in tick_events.end_game_event.iter() {
+        game_over = true;
+        // Unlike the log this leaves out the time taken, which depends on the playback speed
+        events.push(match (&event.winner, event.out_of_time) {
+            (Some(winner), _) => format!("Game over!  Winner: {}s", winner),
+            (None, true) => "Game over!  Out of time!".to_string(),
+            (None, false) => "Game over!  Everybody is dead!".to_string(),
+        });
+    }
+
+    let creatures = creature_query
+        .iter()
+        .map(|(name, position, level, hp)| CreatureRecord {
+            name: name.0.clone(),
+            position: position.cloned(),
+            level: level.cloned(),
+            hp: hp.0,
+        })
+        .collect();
+
+    let record = TickRecord {
+        tick: tick_count.0,
+        events,
+        creatures,
+    };
+
+    (record, game_over)
+}
+
+fn spawn_list(spawn_query: &Query<SpawnComponents>) -> Vec<SpawnRecord> {
+    spawn_query
+        .iter()
+        .map(|(name, creature_type, position, level)| SpawnRecord {
+            name: name.0.clone(),
+            creature_type: creature_type.cloned(),
+            position: position.cloned(),
+            level: level.cloned(),
+        })
+        .collect()
+}
+
+// Runs after the update stage so positions assigned on the first tick are in the spawn list
+pub fn record_replay(
+    mut recorder: ResMut<ReplayRecorder>,
+    tick_count: Res<TickCount>,
+    mut tick_events: TickEvents,
+    spawn_query: Query<SpawnComponents>,
+    creature_query: Query<CreatureComponents, With<CreatureType>>,
+) {
+    if recorder.replay.ticks.is_empty() {
+        recorder.replay.spawns = spawn_list(&spawn_query);
+    }
+
+    let (record, game_over) = current_tick(&tick_count, &mut tick_events, &creature_query);
+    recorder.replay.ticks.push(record);
+
+    if game_over {
+        let contents =
+            ron::ser::to_string_pretty(&recorder.replay, ron::ser::PrettyConfig::default())
+                .expect("Replays can always be serialized");
+
+        if let Err(error) = fs::write(&recorder.path, contents) {
+            eprintln!(
+                "Could not save the replay to {}: {}",
+                recorder.path.display(),
+                error
+            );
+        }
+    }
+}
+
+pub fn check_replay(
+    mut playback: ResMut<ReplayPlayback>,
+    mut log: ResMut<Vec<String>>,
+    tick_count: Res<TickCount>,
+    mut tick_events: TickEvents,
+    spawn_query: Query<SpawnComponents>,
+    creature_query: Query<CreatureComponents, With<CreatureType>>,
+) {
+    let (record, _) = current_tick(&tick_count, &mut tick_events, &creature_query);
+
+    // Once diverged every later tick will differ too, so only the first one is reported
+    if playback.diverged_at.is_some() {
+        return;
+    }
+
+    let spawns_match = tick_count.0 != 1 || spawn_list(&spawn_query) == playback.replay.spawns;
+    let tick_matches = playback
+        .replay
+        .ticks
+        .iter()
+        .find(|recorded| recorded.tick == record.tick)
+        == Some(&record);
+
+    if !spawns_match || !tick_matches {
+        playback.diverged_at = Some(tick_count.0);
+        log.push(format!(
+            "Replay diverged from the recording at tick {}",
+            tick_count.0
+        ));
+    }
+}
+
+// Headless playback has no log on screen, so whether the replay held up is printed at the end
+pub fn print_replay_result(
+    playback: Res<ReplayPlayback>,
+    mut end_game_event: EventReader<EndGameEvent>,
+) {
+    for _ in end_game_event.iter() {
+        match playback.diverged_at {
+            Some(tick) => println!("Replay diverged from the recording at tick {}", tick),
+            None => println!(
+                "Replay matched the recording for all {} ticks",
+                playback.replay.ticks.len()
+            ),
+        }
+    }
+}
+
+fn draw_playback_status(app: &App, paused: bool, finished: bool, tick_delay: Duration) {
+    let map_height = app.world.get_resource::<Dungeon>().unwrap().height();
+    let tick = app.world.get_resource::<TickCount>().unwrap().0;
+    let total_ticks = app
+        .world
+        .get_resource::<ReplayPlayback>()
+        .unwrap()
+        .replay
+        .ticks
+        .len();
+
+    let state = if finished {
+        "Finished".to_string()
+    } else if paused {
+        "Paused".to_string()
+    } else {
+        format!("Playing at {}ms per tick", tick_delay.as_millis())
+    };
+
+    let mut stdout = stdout();
+
+    stdout
+        .queue(style::SetForegroundColor(Color::White))
+        .unwrap()
+        .queue(cursor::MoveTo(0, (map_height + 8).try_into().unwrap()))
+        .unwrap()
+        .queue(style::Print(format!(
+            "Replay tick {}/{} - {: <30} [space] pause  [n] step  [+/-] speed  [q] quit",
+            tick, total_ticks, state
+        )))
+        .unwrap()
+        .flush()
+        .unwrap();
+}
+
+/// Replaces the schedule runner during playback. Each `app.update()` plays exactly one tick, so
+/// pausing, stepping and changing speed only decide when the next update happens.
+pub fn playback_runner(mut app: App) {
+    terminal::enable_raw_mode().unwrap();
+
+    let mut app_exit_event_reader = ManualEventReader::<AppExit>::default();
+    let mut tick_delay =
+        Duration::from_millis(app.world.get_resource::<Scenario>().unwrap().tick_ms);
+    let mut last_tick: Option<Instant> = None;
+    let mut paused = false;
+    let mut step = false;
+    let mut finished = false;
+
+    'playback: loop {
+        while event::poll(Duration::from_millis(0)).unwrap() {
+            if let Event::Key(key) = event::read().unwrap() {
+                match key.code {
+                    KeyCode::Char(' ') => paused = !paused,
+                    KeyCode::Char('n') | KeyCode::Right => {
+                        paused = true;
+                        step = true;
+                    }
+                    KeyCode::Char('+') | KeyCode::Up => {
+                        tick_delay = max(tick_delay / 2, MIN_TICK_DELAY)
+                    }
+                    KeyCode::Char('-') | KeyCode::Down => {
+                        tick_delay = min(tick_delay * 2, MAX_TICK_DELAY)
+                    }
+                    KeyCode::Char('q') | KeyCode::Esc => break 'playback,
+                    // Raw mode swallows the interrupt signal
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        break 'playback
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let tick_due = !paused
+            && match last_tick {
+                Some(last_tick) => last_tick.elapsed() >= tick_delay,
+                None => true,
+            };
+
+        if !finished && (tick_due || step) {
+            step = false;
+            last_tick = Some(Instant::now());
+
+            app.update();
+
+            // Stay on the final tick until the viewer quits
+            if let Some(app_exit_events) = app.world.get_resource::<Events<AppExit>>() {
+                if app_exit_event_reader
+                    .iter(app_exit_events)
+                    .next_back()
+                    .is_some()
+                {
+                    finished = true;
+                }
+            }
+        }
+
+        draw_playback_status(&app, paused, finished, tick_delay);
+
+        std::thread::sleep(MIN_TICK_DELAY);
+    }
+
+    terminal::disable_raw_mode().unwrap();
+}
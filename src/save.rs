@@ -0,0 +1,453 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use bevy::prelude::{Commands, Entity, EventReader, Query, Res};
+use rand_chacha::ChaCha12Rng;
+use rltk::{Algorithm2D, Point};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    combat::{Aggression, Dead, Hp},
+    components::Name,
+    creature::{CombatStats, CreatureType},
+    destination::Destination,
+    equipment::{
+        Armour, EquippedArmour, EquippedShield, EquippedWeapon, Equips, ItemCatalogue, Shield,
+        Weapon,
+    },
+    flow::Flow,
+    fov::Viewshed,
+    map::{Dungeon, Level, Map},
+    path::{Moves, Path},
+    position::Position,
+    rect::Rect,
+    render::Render,
+    spawner::Tracked,
+    EndGameEvent, TickCount,
+};
+
+// Bump this whenever the layout of `SaveGame` changes
+pub const SAVE_VERSION: u32 = 6;
+
+// Where to write the save file, and on which tick. Without a tick the game is saved once it's over.
+pub struct SaveSettings {
+    pub path: PathBuf,
+    pub at_tick: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedMap {
+    pub width: i32,
+    pub height: i32,
+    pub rows: Vec<String>,
+    pub rooms: Vec<Rect>,
+    // Older saves without this still load, their creatures have all spawned already
+    #[serde(default)]
+    pub spawn_zones: Vec<Rect>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedViewshed {
+    pub visible_tiles: Vec<(i32, i32)>,
+    pub range: i32,
+}
+
+// Every game component an entity might have. Creatures, loose items and anything else with a
+// name all use the same layout.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SavedEntity {
+    pub name: String,
+    pub hp: Option<i32>,
+    pub render: Option<Render>,
+    pub creature_type: Option<CreatureType>,
+    pub combat_stats: Option<CombatStats>,
+    pub aggression: Option<i32>,
+    pub viewshed: Option<SavedViewshed>,
+    pub position: Option<Position>,
+    pub level: Option<Level>,
+    pub destination: Option<Position>,
+    // Where in the list of entities the creature being chased is
+    pub destination_target: Option<usize>,
+    pub path: Option<Path>,
+    pub flow: Option<Flow>,
+    pub weapon: Option<Weapon>,
+    pub armour: Option<Armour>,
+    pub shield: Option<Shield>,
+    pub equipped_weapon: Option<Weapon>,
+    pub equipped_armour: Option<Armour>,
+    pub equipped_shield: Option<Shield>,
+    pub moves: bool,
+    pub equips: bool,
+    pub dead: bool,
+    pub tracked: bool,
+}
+
+/// Everything needed to carry on a battle from the tick it was saved on, rolling the same random
+/// numbers it would have if it had never stopped.
+#[derive(Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: u32,
+    pub rng: ChaCha12Rng,
+    pub tick_count: i32,
+    // Every level of the dungeon, from the top down
+    pub levels: Vec<SavedMap>,
+    pub log: Vec<String>,
+    pub entities: Vec<SavedEntity>,
+}
+
+// Reads a save file, checking its version, levels and items before anything is spawned from it
+pub fn load_game(path: &PathBuf, items: &ItemCatalogue) -> Result<(SaveGame, Dungeon), String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+
+    let save: SaveGame = ron::de::from_str(&contents)
+        .map_err(|error| format!("Could not parse {}: {}", path.display(), error))?;
+
+    if save.version != SAVE_VERSION {
+        return Err(format!(
+            "{} is a version {} save, but this game reads version {}",
+            path.display(),
+            save.version,
+            SAVE_VERSION
+        ));
+    }
+
+    if save.levels.is_empty() {
+        return Err(format!("{} has no dungeon levels", path.display()));
+    }
+
+    // Every level is the size the first one says it is
+    let (width, height) = (save.levels[0].width, save.levels[0].height);
+    let mut levels = Vec::new();
+    for (level, saved) in save.levels.iter().enumerate() {
+        let map = Map::from_rows(&saved.rows, saved.rooms.clone(), saved.spawn_zones.clone())
+            .map_err(|error| {
+                format!(
+                    "Could not load level {} in {}: {}",
+                    Level(level),
+                    path.display(),
+                    error
+                )
+            })?;
+
+        if map.width != width || map.height != height {
+            return Err(format!(
+                "Level {} in {} should be {}x{} but is {}x{}",
+                Level(level),
+                path.display(),
+                width,
+                height,
+                map.width,
+                map.height
+            ));
+        }
+
+        levels.push(map);
+    }
+
+    for entity in save.entities.iter() {
+        // Anything placed on the map has to be somewhere on its level
+        let level = entity.level.unwrap_or_default();
+        let map = levels.get(level.0).ok_or_else(|| {
+            format!(
+                "{} has {} on level {}, but the dungeon has only {} levels",
+                path.display(),
+                entity.name,
+                level,
+                levels.len()
+            )
+        })?;
+        let path_steps = entity
+            .path
+            .iter()
+            .flat_map(|saved_path| saved_path.current.iter())
+            .map(|(x, y)| Position(*x, *y));
+        let outside = entity
+            .position
+            .iter()
+            .chain(entity.destination.iter())
+            .chain(entity.path.iter().map(|saved_path| &saved_path.destination))
+            .cloned()
+            .chain(path_steps)
+            .find(|Position(x, y)| !map.in_bounds(Point::new(*x, *y)));
+        if let Some(Position(x, y)) = outside {
+            return Err(format!(
+                "{} has {} at {},{}, which is off the edge of level {}",
+                path.display(),
+                entity.name,
+                x,
+                y,
+                level
+            ));
+        }
+
+        if let Some(index) = entity.destination_target {
+            if index >= save.entities.len() {
+                return Err(format!(
+                    "{} has {} chasing entity {}, but there are only {}",
+                    path.display(),
+                    entity.name,
+                    index,
+                    save.entities.len()
+                ));
+            }
+        }
+
+        let weapons = entity.weapon.iter().chain(entity.equipped_weapon.iter());
+        let armour = entity.armour.iter().chain(entity.equipped_armour.iter());
+        let shields = entity.shield.iter().chain(entity.equipped_shield.iter());
+
+        let unknown_item = weapons
+            .filter(|weapon| !items.has_weapon(weapon))
+            .map(|weapon| weapon.to_string())
+            .chain(
+                armour
+                    .filter(|armour| !items.has_armour(armour))
+                    .map(|armour| armour.to_string()),
+            )
+            .chain(
+                shields
+                    .filter(|shield| !items.has_shield(shield))
+                    .map(|shield| shield.to_string()),
+            )
+            .next();
+
+        if let Some(item) = unknown_item {
+            return Err(format!(
+                "{} has {}, which is not in the item catalogue",
+                path.display(),
+                item
+            ));
+        }
+    }
+
+    Ok((save, Dungeon { levels }))
+}
+
+// Every component a saved entity might have, grouped to keep the query's tuples small
+type SavedComponents<'a> = (
+    Entity,
+    (
+        &'a Name,
+        Option<&'a Hp>,
+        Option<&'a Render>,
+        Option<&'a CreatureType>,
+        Option<&'a CombatStats>,
+        Option<&'a Aggression>,
+        Option<&'a Viewshed>,
+    ),
+    (
+        Option<&'a Position>,
+        Option<&'a Level>,
+        Option<&'a Destination>,
+        Option<&'a Path>,
+        Option<&'a Flow>,
+    ),
+    (
+        Option<&'a Weapon>,
+        Option<&'a Armour>,
+        Option<&'a Shield>,
+        Option<&'a EquippedWeapon>,
+        Option<&'a EquippedArmour>,
+        Option<&'a EquippedShield>,
+    ),
+    (
+        Option<&'a Moves>,
+        Option<&'a Equips>,
+        Option<&'a Dead>,
+        Option<&'a Tracked>,
+    ),
+);
+
+// Runs after the update stage so the commands issued during the tick have been applied
+pub fn save_game(
+    settings: Res<SaveSettings>,
+    tick_count: Res<TickCount>,
+    dungeon: Res<Dungeon>,
+    log: Res<Vec<String>>,
+    rng: Res<ChaCha12Rng>,
+    mut end_game_event: EventReader<EndGameEvent>,
+    query: Query<SavedComponents>,
+) {
+    let game_over = end_game_event.iter().count() > 0;
+    let due = match settings.at_tick {
+        Some(at_tick) => at_tick == tick_count.0,
+        None => game_over,
+    };
+    if !due {
+        return;
+    }
+
+    // Entities are saved in the order they were spawned in, so a loaded game spawns them in the
+    // same order, and each one's place in the list stands in for it
+    let mut saved: Vec<_> = query.iter().collect();
+    saved.sort_by_key(|(entity, ..)| *entity);
+
+    let indices: HashMap<Entity, usize> = saved
+        .iter()
+        .enumerate()
+        .map(|(index, (entity, ..))| (*entity, index))
+        .collect();
+
+    let entities = saved
+        .into_iter()
+        .map(
+            |(
+                _,
+                (name, hp, render, creature_type, combat_stats, aggression, viewshed),
+                (position, level, destination, path, flow),
+                (weapon, armour, shield, equipped_weapon, equipped_armour, equipped_shield),
+                (moves, equips, dead, tracked),
+            )| SavedEntity {
+                name: name.0.clone(),
+                hp: hp.map(|hp| hp.0),
+                render: render.cloned(),
+                creature_type: creature_type.cloned(),
+                combat_stats: combat_stats.cloned(),
+                aggression: aggression.map(|aggression| aggression.0),
+                viewshed: viewshed.map(|viewshed| SavedViewshed {
+                    visible_tiles: viewshed
+                        .visible_tiles
+                        .iter()
+                        .map(|point| (point.x, point.y))
+                        .collect(),
+                    range: viewshed.range,
+                }),
+                position: position.cloned(),
+                level: level.cloned(),
+                destination: destination.map(|destination| destination.position.clone()),
+                destination_target: destination
+                    .and_then(|destination| destination.target)
+                    .and_then(|target| indices.get(&target).copied()),
+                path: path.cloned(),
+                flow: flow.cloned(),
+                weapon: weapon.cloned(),
+                armour: armour.cloned(),
+                shield: shield.cloned(),
+                equipped_weapon: equipped_weapon.map(|equipped| equipped.0.clone()),
+                equipped_armour: equipped_armour.map(|equipped| equipped.0.clone()),
+                equipped_shield: equipped_shield.map(|equipped| equipped.0.clone()),
+                moves: moves.is_some(),
+                equips: equips.is_some(),
+                dead: dead.is_some(),
+                tracked: tracked.is_some(),
+            },
+        )
+        .collect();
+
+    let save = SaveGame {
+        version: SAVE_VERSION,
+        rng: rng.clone(),
+        tick_count: tick_count.0,
+        levels: dungeon
+            .levels
+            .iter()
+            .map(|map| SavedMap {
+                width: map.width,
+                height: map.height,
+                rows: map.to_rows(),
+                rooms: map.rooms.clone(),
+                spawn_zones: map.spawn_zones.clone(),
+            })
+            .collect(),
+        log: log.clone(),
+        entities,
+    };
+
+    let contents = ron::ser::to_string_pretty(&save, ron::ser::PrettyConfig::default())
+        .expect("Game state can always be serialized");
+
+    if let Err(error) = fs::write(&settings.path, contents) {
+        eprintln!("Could not save to {}: {}", settings.path.display(), error);
+    }
+}
+
+// Startup system that replaces `spawn_all` when a game is loaded
+pub fn restore_entities(mut commands: Commands, save: Res<SaveGame>) {
+    let mut spawned = Vec::new();
+
+    for saved in save.entities.iter() {
+        let mut entity = commands.spawn();
+        spawned.push(entity.id());
+        entity.insert(Name(saved.name.clone()));
+
+        if let Some(hp) = saved.hp {
+            entity.insert(Hp(hp));
+        }
+        if let Some(render) = &saved.render {
+            entity.insert(render.clone());
+        }
+        if let Some(creature_type) = &saved.creature_type {
+            entity.insert(creature_type.clone());
+        }
+        if let Some(combat_stats) = &saved.combat_stats {
+            entity.insert(combat_stats.clone());
+        }
+        if let Some(aggression) = saved.aggression {
+            entity.insert(Aggression(aggression));
+        }
+        if let Some(viewshed) = &saved.viewshed {
+            entity.insert(Viewshed {
+                visible_tiles: viewshed
+                    .visible_tiles
+                    .iter()
+                    .map(|&(x, y)| Point::new(x, y))
+                    .collect(),
+                range: viewshed.range,
+            });
+        }
+        if let Some(position) = &saved.position {
+            entity.insert(position.clone());
+        }
+        if let Some(level) = saved.level {
+            entity.insert(level);
+        }
+        if let Some(path) = &saved.path {
+            entity.insert(path.clone());
+        }
+        if let Some(flow) = saved.flow {
+            entity.insert(flow);
+        }
+        if let Some(weapon) = &saved.weapon {
+            entity.insert(weapon.clone());
+        }
+        if let Some(armour) = &saved.armour {
+            entity.insert(armour.clone());
+        }
+        if let Some(shield) = &saved.shield {
+            entity.insert(shield.clone());
+        }
+        if let Some(weapon) = &saved.equipped_weapon {
+            entity.insert(EquippedWeapon(weapon.clone()));
+        }
+        if let Some(armour) = &saved.equipped_armour {
+            entity.insert(EquippedArmour(armour.clone()));
+        }
+        if let Some(shield) = &saved.equipped_shield {
+            entity.insert(EquippedShield(shield.clone()));
+        }
+        if saved.moves {
+            entity.insert(Moves);
+        }
+        if saved.equips {
+            entity.insert(Equips);
+        }
+        if saved.dead {
+            entity.insert(Dead);
+        }
+        if saved.tracked {
+            entity.insert(Tracked);
+        }
+    }
+
+    // A destination can chase another entity, so destinations go on once everything is spawned
+    for (saved, &entity) in save.entities.iter().zip(spawned.iter()) {
+        if let Some(destination) = &saved.destination {
+            commands.entity(entity).insert(Destination {
+                position: destination.clone(),
+                target: saved.destination_target.map(|index| spawned[index]),
+            });
+        }
+    }
+
+    commands.remove_resource::<SaveGame>();
+}
@@ -0,0 +1,293 @@
+use std::{collections::HashSet, fs, path::Path};
+
+use serde::Deserialize;
+
+use crate::{
+    creature::CreatureTemplate,
+    equipment::ItemCatalogue,
+    map::{Level, MapSettings, PrefabSpawn},
+    path::Movement,
+};
+
+// Used when no scenario is picked on the command line
+pub const DEFAULT_SCENARIO_PATH: &str = "assets/scenarios/default.ron";
+
+// How many creatures to spawn from one of the templates in the creature data file. The template's
+// creature_type decides which faction they fight for.
+#[derive(Clone, Deserialize)]
+pub struct RosterEntry {
+    pub creature: String,
+    pub count: u32,
+    // The dungeon level they start on, counting from 1 at the top
+    #[serde(default = "top_level")]
+    pub level: usize,
+}
+
+fn top_level() -> usize {
+    1
+}
+
+impl RosterEntry {
+    pub fn start_level(&self) -> Level {
+        Level(self.level - 1)
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct LootEntry {
+    pub item: String,
+    // Relative chance of this item being picked each time a piece of loot is spawned. Without one
+    // the item's spawn_weight from the item catalogue is used.
+    #[serde(default)]
+    pub weight: Option<u32>,
+}
+
+impl LootEntry {
+    pub fn weight(&self, items: &ItemCatalogue) -> u32 {
+        self.weight
+            .unwrap_or_else(|| items.spawn_weight(&self.item))
+    }
+}
+
+// The items scattered around the map at the start of a battle
+#[derive(Clone, Deserialize)]
+pub struct LootTable {
+    pub count: u32,
+    pub items: Vec<LootEntry>,
+}
+
+#[derive(Clone, Deserialize)]
+pub enum VictoryCondition {
+    // The last creature type with anyone left alive wins
+    LastFactionStanding,
+    // The game stops after this many ticks and the creature type with the most survivors wins
+    TickLimit(i32),
+}
+
+fn default_repath_budget() -> usize {
+    8
+}
+
+/// Everything about how a battle is set up, read from a scenario file.
+#[derive(Clone, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    pub map: MapSettings,
+    pub roster: Vec<RosterEntry>,
+    pub loot: LootTable,
+    // Whether creatures can move diagonally as well as along the rows and columns of the map
+    #[serde(default)]
+    pub movement: Movement,
+    // How many creatures' paths can be worked out again each tick once they've gone out of date
+    #[serde(default = "default_repath_budget")]
+    pub repath_budget: usize,
+    // How long each tick lasts when the battle is drawn in the terminal
+    pub tick_ms: u64,
+    // The game ends as soon as any one of these is met
+    pub victory: Vec<VictoryCondition>,
+    // The name of the creature to show details of under the map. Without one the first creature
+    // from a template marked as tracked is shown.
+    #[serde(default)]
+    pub tracked: Option<String>,
+}
+
+impl Scenario {
+    // The names every creature in the roster will be spawned with
+    pub fn creature_names(&self, creatures: &[CreatureTemplate]) -> Vec<String> {
+        self.roster
+            .iter()
+            .filter_map(|entry| {
+                creatures
+                    .iter()
+                    .find(|template| template.id == entry.creature)
+                    .map(|template| (template, entry.count))
+            })
+            .flat_map(|(template, count)| (1..=count).map(move |number| template.get_name(number)))
+            .collect()
+    }
+
+    // How many creatures in the roster start on each level of the dungeon, from the top down
+    pub fn starting_creatures(&self) -> Vec<usize> {
+        let mut creatures = vec![0; self.map.levels as usize];
+        for entry in self.roster.iter() {
+            if let Some(count) = creatures.get_mut(entry.start_level().0) {
+                *count += entry.count as usize;
+            }
+        }
+        creatures
+    }
+
+    pub fn check_tracked(&self, creatures: &[CreatureTemplate]) -> Result<(), String> {
+        match &self.tracked {
+            Some(tracked) if !self.creature_names(creatures).contains(tracked) => Err(format!(
+                "There is no creature called {} in the {} scenario",
+                tracked, self.name
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Roster and loot names are checked against the data files so a typo is caught up front
+pub fn load_scenario(
+    path: &Path,
+    creatures: &[CreatureTemplate],
+    items: &ItemCatalogue,
+) -> Result<Scenario, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|error| format!("Could not read {}: {}", path.display(), error))?;
+
+    let mut scenario: Scenario = ron::de::from_str(&contents)
+        .map_err(|error| format!("Could not parse {}: {}", path.display(), error))?;
+
+    scenario.map.load_files()?;
+    scenario
+        .map
+        .validate()
+        .map_err(|error| format!("{}: {}", path.display(), error))?;
+
+    for prefab in scenario.map.prefabs() {
+        for spawn in prefab.markers.values() {
+            let (name, known, file) = match spawn {
+                PrefabSpawn::Creature(id) => (
+                    id,
+                    creatures.iter().any(|template| template.id == *id),
+                    "the creature data file",
+                ),
+                PrefabSpawn::Item(name) => (name, items.has_item(name), "the item catalogue"),
+            };
+
+            if !known {
+                return Err(format!(
+                    "{}: the {} prefab spawns {}, which is not in {}",
+                    path.display(),
+                    prefab.name,
+                    name,
+                    file
+                ));
+            }
+        }
+    }
+
+    let mut factions = HashSet::new();
+    for entry in scenario.roster.iter() {
+        match creatures
+            .iter()
+            .find(|template| template.id == entry.creature)
+        {
+            Some(_) if entry.level < 1 || entry.level > scenario.map.levels as usize => {
+                return Err(format!(
+                    "{}: {} start on level {}, but the dungeon has levels 1 to {}",
+                    path.display(),
+                    entry.creature,
+                    entry.level,
+                    scenario.map.levels
+                ))
+            }
+            Some(template) if entry.count > 0 => {
+                factions.insert(&template.creature_type);
+            }
+            Some(_) => {}
+            None => {
+                return Err(format!(
+                    "{}: {} is not in the creature data file",
+                    path.display(),
+                    entry.creature
+                ))
+            }
+        }
+    }
+
+    if factions.len() < 2 {
+        return Err(format!(
+            "{}: the roster needs creatures of at least two types to have a battle",
+            path.display()
+        ));
+    }
+
+    for entry in scenario.loot.items.iter() {
+        if !items.has_item(&entry.item) {
+            return Err(format!(
+                "{}: {} is not in the item catalogue",
+                path.display(),
+                entry.item
+            ));
+        }
+    }
+
+    let total_weight: u32 = scenario
+        .loot
+        .items
+        .iter()
+        .map(|entry| entry.weight(items))
+        .sum();
+    if scenario.loot.count > 0 && total_weight == 0 {
+        return Err(format!(
+            "{}: loot is spawned but no item has a weight",
+            path.display()
+        ));
+    }
+
+    scenario
+        .check_tracked(creatures)
+        .map_err(|error| format!("{}: {}", path.display(), error))?;
+
+    if scenario.tick_ms == 0 {
+        return Err(format!("{}: tick_ms must be more than 0", path.display()));
+    }
+
+    if scenario.repath_budget == 0 {
+        return Err(format!(
+            "{}: repath_budget must be more than 0",
+            path.display()
+        ));
+    }
+
+    if scenario.victory.is_empty() {
+        return Err(format!(
+            "{}: needs at least one victory condition or the battle never ends",
+            path.display()
+        ));
+    }
+
+    Ok(scenario)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+    use crate::{creature::load_creature_templates, equipment::load_item_catalogue};
+
+    fn data() -> (Vec<CreatureTemplate>, ItemCatalogue) {
+        let items = load_item_catalogue(Path::new("assets/items.ron")).unwrap();
+        let creatures = load_creature_templates(Path::new("assets/creatures.ron"), &items).unwrap();
+        (creatures, items)
+    }
+
+    #[test]
+    fn the_scenario_files_load() {
+        let (creatures, items) = data();
+
+        for entry in fs::read_dir("assets/scenarios").unwrap() {
+            let path = entry.unwrap().path();
+            if let Err(error) = load_scenario(&path, &creatures, &items) {
+                panic!("{}", error);
+            }
+        }
+    }
+
+    #[test]
+    fn a_repath_budget_of_nothing_is_rejected() {
+        let (creatures, items) = data();
+        let contents = fs::read_to_string(DEFAULT_SCENARIO_PATH)
+            .unwrap()
+            .replace("tick_ms: 300,", "tick_ms: 300,\n    repath_budget: 0,");
+        let path = env::temp_dir().join("no_repath_budget.ron");
+        fs::write(&path, contents).unwrap();
+
+        let error = load_scenario(&path, &creatures, &items).err().unwrap();
+        assert!(error.contains("repath_budget"), "{}", error);
+    }
+}
@@ -1,7 +1,29 @@
-use bevy::prelude::{Bundle, Commands};
-use crossterm::style::Color;
+// Bevy's Bundle derive forgets each field once it's been moved into the world
+#![allow(clippy::forget_non_drop)]
 
-use crate::{combat::*, components::*, creature::CreatureType, equipment::{Armour, EquippedWeapon, Equips, Shield, Weapon}, fov::Viewshed, path::Moves, render::Render};
+use std::collections::HashMap;
+
+use bevy::{
+    ecs::system::EntityCommands,
+    prelude::{Bundle, Commands, Res, ResMut},
+};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand_chacha::ChaCha12Rng;
+
+use crate::{
+    combat::*,
+    components::*,
+    creature::{CombatStats, CreatureTemplate, CreatureType},
+    equipment::{
+        Armour, EquippedArmour, EquippedShield, EquippedWeapon, Equips, ItemCatalogue, Shield,
+        Weapon,
+    },
+    fov::Viewshed,
+    map::{Dungeon, Level, PrefabSpawn},
+    path::Moves,
+    render::Render,
+    scenario::{LootTable, Scenario},
+};
 
 #[derive(Bundle)]
 struct CreatureBundle {
@@ -11,107 +33,180 @@ struct CreatureBundle {
     moves: Moves,
     aggression: Aggression,
     creature_type: CreatureType,
+    combat_stats: CombatStats,
     viewshed: Viewshed,
     equips: Equips,
 }
 
 pub struct Tracked;
 
-fn spawn_humans(commands: &mut Commands) {
-    for _ in 1..=4 {
-        commands
-            .spawn_bundle(CreatureBundle {
-                name: Name(String::from(format!("Human"))),
-                hp: Hp(15),
-                render: Render {
-                    colour: Color::Green,
-                    char: "H".to_string(),
-                },
-                moves: Moves,
-                aggression: Aggression(100),
-                viewshed: Viewshed {
-                    visible_tiles: Vec::new(),
-                    range: 4,
-                },
-                creature_type: CreatureType::Human,
-                equips: Equips,
-            })
-            .insert(EquippedWeapon(Weapon::Sword));
+fn spawn_creature<'a, 'b>(
+    commands: &'b mut Commands<'a>,
+    template: &CreatureTemplate,
+    name: String,
+) -> EntityCommands<'a, 'b> {
+    let mut creature = commands.spawn_bundle(CreatureBundle {
+        name: Name(name),
+        hp: Hp(template.hp),
+        render: Render {
+            colour: template.colour,
+            char: template.glyph.clone(),
+        },
+        moves: Moves,
+        aggression: Aggression(template.aggression),
+        viewshed: Viewshed {
+            visible_tiles: Vec::new(),
+            range: template.vision_range,
+        },
+        creature_type: template.creature_type.clone(),
+        combat_stats: template.stats.clone(),
+        equips: Equips,
+    });
+
+    if let Some(weapon) = &template.weapon {
+        creature.insert(EquippedWeapon(weapon.clone()));
+    }
+    if let Some(armour) = &template.armour {
+        creature.insert(EquippedArmour(armour.clone()));
+    }
+    if let Some(shield) = &template.shield {
+        creature.insert(EquippedShield(shield.clone()));
     }
+
+    creature
 }
 
-fn spawn_goblins(commands: &mut Commands) {
-    for i in 1..=4 {
-        commands
-            .spawn_bundle(CreatureBundle {
-                name: Name(String::from(format!("Goblin{}", i))),
-                hp: Hp(15),
-                render: Render {
-                    colour: Color::Red,
-                    char: "G".to_string(),
-                },
-                moves: Moves,
-                aggression: Aggression(100),
-                viewshed: Viewshed {
-                    visible_tiles: Vec::new(),
-                    range: 4,
-                },
-                creature_type: CreatureType::Goblin,
-                equips: Equips,
-            })
-            .insert(EquippedWeapon(Weapon::Sword));
+fn spawn_creatures(commands: &mut Commands, scenario: &Scenario, templates: &[CreatureTemplate]) {
+    let mut tracking = false;
+
+    for entry in scenario.roster.iter() {
+        // Scenarios are checked against the creature templates when loaded
+        let template = match templates
+            .iter()
+            .find(|template| template.id == entry.creature)
+        {
+            Some(template) => template,
+            None => continue,
+        };
+
+        for number in 1..=entry.count {
+            let name = template.get_name(number);
+
+            // A creature picked by name in the scenario takes priority over the templates
+            let tracked = match &scenario.tracked {
+                Some(tracked_name) => *tracked_name == name,
+                None => template.tracked,
+            };
+
+            let mut creature = spawn_creature(commands, template, name);
+            creature.insert(entry.start_level());
+
+            if tracked && !tracking {
+                creature.insert(Tracked);
+                tracking = true;
+            }
+        }
     }
 }
 
-fn spawn_orcs(commands: &mut Commands) {
-    commands
-        .spawn_bundle(CreatureBundle {
-            name: Name(String::from(format!("Special Orc"))),
-            hp: Hp(15),
-            render: Render {
-                colour: Color::Cyan,
-                char: "O".to_string(),
-            },
-            moves: Moves,
-            aggression: Aggression(100),
-            viewshed: Viewshed {
-                visible_tiles: Vec::new(),
-                range: 6,
-            },
-            creature_type: CreatureType::Orc,
-            equips: Equips,
-        })
-        .insert(EquippedWeapon(Weapon::GreatHammer))
-        .insert(Tracked);
-}
+// The catalogue is checked for the item's name when the scenario is loaded
+fn spawn_item<'a, 'b>(
+    commands: &'b mut Commands<'a>,
+    items: &ItemCatalogue,
+    name: &str,
+) -> EntityCommands<'a, 'b> {
+    let name = name.to_string();
 
-fn spawn_weapons(commands: &mut Commands) {
-    for _ in 1..=2 {
-        commands.spawn_bundle(Weapon::Nunchucks.get_bundle());
-    }
-    for _ in 1..=40 {
-        commands.spawn_bundle(Weapon::GreatHammer.get_bundle());
+    if items.has_weapon(&Weapon(name.clone())) {
+        commands.spawn_bundle(items.weapon_bundle(&Weapon(name)))
+    } else if items.has_armour(&Armour(name.clone())) {
+        commands.spawn_bundle(items.armour_bundle(&Armour(name)))
+    } else {
+        commands.spawn_bundle(items.shield_bundle(&Shield(name)))
     }
 }
 
-fn spawn_armour(commands: &mut Commands) {
-    for _ in 1..=6 {
-        commands.spawn_bundle(Armour::ChainMail.get_bundle());
-        commands.spawn_bundle(Armour::PlateMail.get_bundle());
+// Loot is shared out evenly between the levels of the dungeon
+fn spawn_loot(
+    commands: &mut Commands,
+    loot: &LootTable,
+    items: &ItemCatalogue,
+    levels: usize,
+    rng: &mut ChaCha12Rng,
+) {
+    // Scenarios are checked for loot weights when loaded, so this only fails without loot
+    let weights = loot.items.iter().map(|entry| entry.weight(items));
+    let distribution = match WeightedIndex::new(weights) {
+        Ok(distribution) => distribution,
+        Err(_) => return,
+    };
+
+    for number in 0..loot.count as usize {
+        spawn_item(commands, items, &loot.items[distribution.sample(rng)].item)
+            .insert(Level(number % levels));
     }
 }
 
-fn spawn_shields(commands: &mut Commands) {
-    for _ in 1..=20 {
-        commands.spawn_bundle(Shield::Buckler.get_bundle());
+// Creatures and items on the markers of prefabs stamped into the map. Creatures are numbered on
+// from the ones in the roster.
+fn spawn_prefab_markers(
+    commands: &mut Commands,
+    dungeon: &Dungeon,
+    scenario: &Scenario,
+    templates: &[CreatureTemplate],
+    items: &ItemCatalogue,
+) {
+    let mut numbers: HashMap<&str, u32> = HashMap::new();
+    for entry in scenario.roster.iter() {
+        *numbers.entry(&entry.creature).or_insert(0) += entry.count;
+    }
+
+    for (level, map) in dungeon.levels.iter().enumerate() {
+        for (position, spawn) in map.prefab_spawns.iter() {
+            match spawn {
+                PrefabSpawn::Creature(id) => {
+                    let template = match templates.iter().find(|template| template.id == *id) {
+                        Some(template) => template,
+                        None => continue,
+                    };
+
+                    let number = numbers.entry(id).or_insert(0);
+                    *number += 1;
+                    spawn_creature(commands, template, template.get_name(*number))
+                        .insert(position.clone())
+                        .insert(Level(level));
+                }
+                PrefabSpawn::Item(name) => {
+                    spawn_item(commands, items, name)
+                        .insert(position.clone())
+                        .insert(Level(level));
+                }
+            }
+        }
     }
 }
 
-pub fn spawn_all(mut commands: Commands) {
-    spawn_humans(&mut commands);
-    spawn_goblins(&mut commands);
-    spawn_orcs(&mut commands);
-    spawn_weapons(&mut commands);
-    spawn_armour(&mut commands);
-    spawn_shields(&mut commands);
+pub fn spawn_all(
+    mut commands: Commands,
+    scenario: Res<Scenario>,
+    creature_templates: Res<Vec<CreatureTemplate>>,
+    items: Res<ItemCatalogue>,
+    dungeon: Res<Dungeon>,
+    mut rng: ResMut<ChaCha12Rng>,
+) {
+    spawn_creatures(&mut commands, &scenario, &creature_templates);
+    spawn_loot(
+        &mut commands,
+        &scenario.loot,
+        &items,
+        dungeon.levels.len(),
+        &mut rng,
+    );
+    spawn_prefab_markers(
+        &mut commands,
+        &dungeon,
+        &scenario,
+        &creature_templates,
+        &items,
+    );
 }
@@ -0,0 +1,242 @@
+use bevy::prelude::{Commands, Entity, EventWriter, Query, ResMut, With, Without};
+
+use crate::{
+    combat::Dead,
+    components::Name,
+    destination::Destination,
+    flow::Flow,
+    map::{Dungeon, Level},
+    path::{Moves, Path},
+    position::Position,
+};
+
+pub struct StairsEvent {
+    pub entity: Entity,
+    pub name: String,
+    pub from: Level,
+    pub to: Level,
+}
+
+// A creature that could be on its way down or up the stairs
+type Traveller<'a> = (
+    Entity,
+    &'a Name,
+    &'a mut Position,
+    &'a mut Level,
+    &'a Destination,
+);
+
+// Creatures that set out for the stairs take them once they get there, coming out on the stairs
+// leading back the other way. Walking over stairs on the way somewhere else doesn't count. They
+// wait while someone stands on the stairs they'd come out on, unless that creature is taking the
+// stairs too, in which case the two pass each other.
+pub fn take_stairs(
+    mut commands: Commands,
+    mut creature_query: Query<Traveller, (With<Moves>, Without<Dead>)>,
+    mut dungeon: ResMut<Dungeon>,
+    mut stairs_event: EventWriter<StairsEvent>,
+) {
+    // In the order creatures were spawned in, so several taking the stairs at once are logged the
+    // same way every time
+    let mut leaving: Vec<(Entity, Level, Position)> = creature_query
+        .iter_mut()
+        .filter(|(_, _, position, _, destination)| destination.position == **position)
+        .filter_map(|(entity, _, position, level, _)| {
+            dungeon
+                .stairs_exit(&level, &position)
+                .map(|(to, arrival)| (entity, to, arrival))
+        })
+        .collect();
+    leaving.sort_by_key(|(entity, ..)| *entity);
+
+    let arrivals: Vec<(Entity, Level, Position)> = leaving
+        .iter()
+        .filter(|(_, to, arrival)| {
+            let map = dungeon.map(to);
+            match map.occupant(map.xy_idx(arrival.0, arrival.1)) {
+                Some(other) => leaving.iter().any(|(entity, ..)| *entity == other),
+                None => true,
+            }
+        })
+        .cloned()
+        .collect();
+
+    for (entity, to, arrival) in arrivals {
+        let (_, name, mut position, mut level, _) = creature_query.get_mut(entity).unwrap();
+
+        stairs_event.send(StairsEvent {
+            entity,
+            name: name.0.clone(),
+            from: *level,
+            to,
+        });
+
+        let from_map = dungeon.map_mut(&level);
+        let from_idx = from_map.xy_idx(position.0, position.1);
+        if from_map.occupants[from_idx] == Some(entity) {
+            from_map.occupants[from_idx] = None;
+        }
+        let to_map = dungeon.map_mut(&to);
+        let to_idx = to_map.xy_idx(arrival.0, arrival.1);
+        to_map.occupants[to_idx] = Some(entity);
+
+        *level = to;
+        *position = arrival;
+        commands
+            .entity(entity)
+            .remove::<Path>()
+            .remove::<Destination>()
+            .remove::<Flow>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        app::Events,
+        prelude::{IntoSystem, Stage, SystemStage, World},
+    };
+
+    use super::*;
+    use crate::map::{Map, TileType};
+
+    // Two levels with the stairs down at 2,1 on the top one and the stairs up at 3,1 below it
+    fn dungeon() -> Dungeon {
+        let mut top = Map::new(6, 3);
+        let mut bottom = Map::new(6, 3);
+        for x in 1..5 {
+            let idx = top.xy_idx(x, 1);
+            top.tiles[idx] = TileType::Floor;
+            bottom.tiles[idx] = TileType::Floor;
+        }
+        let (down, up) = (top.xy_idx(2, 1), bottom.xy_idx(3, 1));
+        top.tiles[down] = TileType::DownStairs;
+        bottom.tiles[up] = TileType::UpStairs;
+        top.occupants = vec![None; top.tiles.len()];
+        bottom.occupants = vec![None; bottom.tiles.len()];
+
+        Dungeon {
+            levels: vec![top, bottom],
+        }
+    }
+
+    fn creature(
+        world: &mut World,
+        name: &str,
+        level: Level,
+        position: Position,
+        destination: Position,
+    ) -> Entity {
+        let creature = world
+            .spawn()
+            .insert_bundle((
+                Moves,
+                Name(name.to_string()),
+                position.clone(),
+                level,
+                Destination {
+                    position: destination,
+                    target: None,
+                },
+            ))
+            .id();
+
+        let mut dungeon = world.get_resource_mut::<Dungeon>().unwrap();
+        let map = dungeon.map_mut(&level);
+        let idx = map.xy_idx(position.0, position.1);
+        map.occupants[idx] = Some(creature);
+        creature
+    }
+
+    fn take_stairs_once(world: &mut World) {
+        SystemStage::single_threaded()
+            .with_system(take_stairs.system())
+            .run(world);
+    }
+
+    #[test]
+    fn only_creatures_heading_for_the_stairs_take_them() {
+        let mut world = World::default();
+        world.insert_resource(dungeon());
+        world.insert_resource(Events::<StairsEvent>::default());
+        let climber = creature(
+            &mut world,
+            "Climber",
+            Level(0),
+            Position(2, 1),
+            Position(2, 1),
+        );
+        let passer = creature(
+            &mut world,
+            "Passer",
+            Level(0),
+            Position(2, 1),
+            Position(4, 1),
+        );
+
+        take_stairs_once(&mut world);
+
+        assert_eq!(world.get::<Level>(climber), Some(&Level(1)));
+        assert_eq!(world.get::<Position>(climber), Some(&Position(3, 1)));
+        assert!(world.get::<Destination>(climber).is_none());
+
+        assert_eq!(world.get::<Level>(passer), Some(&Level(0)));
+        assert_eq!(world.get::<Position>(passer), Some(&Position(2, 1)));
+        assert!(world.get::<Destination>(passer).is_some());
+
+        let events = world.get_resource::<Events<StairsEvent>>().unwrap();
+        let taken: Vec<_> = events.get_reader().iter(events).map(|e| e.entity).collect();
+        assert_eq!(taken, vec![climber]);
+    }
+
+    #[test]
+    fn creatures_wait_for_someone_standing_on_the_stairs_they_come_out_on() {
+        let mut world = World::default();
+        world.insert_resource(dungeon());
+        world.insert_resource(Events::<StairsEvent>::default());
+        let climber = creature(
+            &mut world,
+            "Climber",
+            Level(0),
+            Position(2, 1),
+            Position(2, 1),
+        );
+        let blocker = creature(
+            &mut world,
+            "Blocker",
+            Level(1),
+            Position(3, 1),
+            Position(1, 1),
+        );
+
+        take_stairs_once(&mut world);
+        assert_eq!(world.get::<Level>(climber), Some(&Level(0)));
+
+        // Once the stairs are clear the way down is open again
+        world.get_mut::<Position>(blocker).unwrap().0 = 1;
+        let mut dungeon = world.get_resource_mut::<Dungeon>().unwrap();
+        let map = dungeon.map_mut(&Level(1));
+        map.move_occupant(blocker, map.xy_idx(3, 1), map.xy_idx(1, 1));
+
+        take_stairs_once(&mut world);
+        assert_eq!(world.get::<Level>(climber), Some(&Level(1)));
+    }
+
+    #[test]
+    fn creatures_taking_the_same_stairs_both_ways_pass_each_other() {
+        let mut world = World::default();
+        world.insert_resource(dungeon());
+        world.insert_resource(Events::<StairsEvent>::default());
+        let down = creature(&mut world, "Down", Level(0), Position(2, 1), Position(2, 1));
+        let up = creature(&mut world, "Up", Level(1), Position(3, 1), Position(3, 1));
+
+        take_stairs_once(&mut world);
+
+        assert_eq!(world.get::<Level>(down), Some(&Level(1)));
+        assert_eq!(world.get::<Level>(up), Some(&Level(0)));
+        let dungeon = world.get_resource::<Dungeon>().unwrap();
+        let (top, bottom) = (dungeon.map(&Level(0)), dungeon.map(&Level(1)));
+        assert_eq!(top.occupant(top.xy_idx(2, 1)), Some(up));
+        assert_eq!(bottom.occupant(bottom.xy_idx(3, 1)), Some(down));
+    }
+}
@@ -0,0 +1,98 @@
+use bevy::prelude::{Entity, EventWriter, Query, Res, ResMut, Without};
+use rand::Rng;
+use rand_chacha::ChaCha12Rng;
+
+use crate::{
+    combat::{Dead, Hp},
+    components::Name,
+    map::{Dungeon, Level, TileType},
+    position::Position,
+};
+
+// Lava burns for 1d6 damage every tick a creature stands in it
+const LAVA_DAMAGE_DIE: i32 = 6;
+
+pub struct BurnEvent {
+    pub entity: Entity,
+    pub name: String,
+    pub damage: i32,
+}
+
+pub fn burn_in_lava(
+    mut creature_query: Query<(Entity, &Name, &Position, &Level, &mut Hp), Without<Dead>>,
+    dungeon: Res<Dungeon>,
+    mut rng: ResMut<ChaCha12Rng>,
+    mut burn_event: EventWriter<BurnEvent>,
+) {
+    // Burns are rolled for in the order creatures were spawned in
+    let mut creatures: Vec<_> = creature_query.iter_mut().collect();
+    creatures.sort_by_key(|(entity, ..)| *entity);
+
+    for (entity, name, position, level, mut hp) in creatures {
+        let map = dungeon.map(level);
+        if map.tiles[map.xy_idx(position.0, position.1)] != TileType::Lava {
+            continue;
+        }
+
+        let damage = rng.gen_range(1..=LAVA_DAMAGE_DIE);
+        hp.0 -= damage;
+
+        burn_event.send(BurnEvent {
+            entity,
+            name: name.0.clone(),
+            damage,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        app::Events,
+        prelude::{IntoSystem, Stage, SystemStage, World},
+    };
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::map::Map;
+
+    #[test]
+    fn only_creatures_in_lava_are_burned() {
+        let mut world = World::default();
+        let mut map = Map::new(4, 3);
+        let lava = map.xy_idx(1, 1);
+        map.tiles[lava] = TileType::Lava;
+        map.tiles[lava + 1] = TileType::Floor;
+        world.insert_resource(Dungeon { levels: vec![map] });
+        world.insert_resource(ChaCha12Rng::seed_from_u64(0));
+        world.insert_resource(Events::<BurnEvent>::default());
+
+        let burning = world
+            .spawn()
+            .insert_bundle((
+                Name("Burning".to_string()),
+                Position(1, 1),
+                Level(0),
+                Hp(10),
+            ))
+            .id();
+        let safe = world
+            .spawn()
+            .insert_bundle((Name("Safe".to_string()), Position(2, 1), Level(0), Hp(10)))
+            .id();
+
+        SystemStage::single_threaded()
+            .with_system(burn_in_lava.system())
+            .run(&mut world);
+
+        let burnt = 10 - world.get::<Hp>(burning).unwrap().0;
+        assert!((1..=LAVA_DAMAGE_DIE).contains(&burnt));
+        assert_eq!(world.get::<Hp>(safe).unwrap().0, 10);
+
+        let events = world.get_resource::<Events<BurnEvent>>().unwrap();
+        let burns: Vec<_> = events.get_reader().iter(events).collect();
+        assert_eq!(burns.len(), 1);
+        assert_eq!(burns[0].entity, burning);
+        assert_eq!(burns[0].damage, burnt);
+    }
+}
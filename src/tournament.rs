@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::{
+    AppBuilder, Commands, EventReader, IntoSystem, ParallelSystemDescriptorCoercion, Query, Res,
+    ResMut,
+};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::Serialize;
+
+use crate::{
+    build_app,
+    cleanup::Survivor,
+    combat::{AttackEvent, AttackOutcome, Death, Hp},
+    creature::CreatureType,
+    output::{print_structured, OutputFormat},
+    EndGameEvent, GameData, GameSettings, GameSetup, Seed, TickCount,
+};
+
+// Games still running after this many ticks are abandoned so a stalemate can't hang the tournament
+const MAX_TICKS: i32 = 10_000;
+
+#[derive(Serialize)]
+pub struct GameResult {
+    // None for games continued from a save
+    pub seed: Option<u64>,
+    // None when the game ended in a draw
+    pub winner: Option<CreatureType>,
+    pub ticks: i32,
+    pub survivors: usize,
+    pub kills_by_weapon: BTreeMap<String, u32>,
+}
+
+// Number of killing blows landed with each weapon, keyed by weapon name
+#[derive(Default)]
+pub struct KillsByWeapon(BTreeMap<String, u32>);
+
+pub fn record_kills(mut kills: ResMut<KillsByWeapon>, mut attack_event: EventReader<AttackEvent>) {
+    for event in attack_event.iter() {
+        if let AttackOutcome::Hit {
+            weapon,
+            killed: true,
+            ..
+        } = &event.outcome
+        {
+            *kills.0.entry(weapon.name.clone()).or_insert(0) += 1;
+        }
+    }
+}
+
+pub fn record_result(
+    mut commands: Commands,
+    mut end_game_event: EventReader<EndGameEvent>,
+    seed: Option<Res<Seed>>,
+    tick_count: Res<TickCount>,
+    kills: Res<KillsByWeapon>,
+    survivor_query: Query<&Hp, Survivor>,
+) {
+    for event in end_game_event.iter() {
+        commands.insert_resource(GameResult {
+            seed: seed.as_ref().map(|seed| seed.0),
+            winner: event.winner.clone(),
+            ticks: tick_count.0,
+            survivors: survivor_query.iter().filter(|hp| !hp.is_dead()).count(),
+            kills_by_weapon: kills.0.clone(),
+        });
+    }
+}
+
+// Keeps track of the game so a GameResult is inserted once it ends
+pub fn add_result_systems(app: &mut AppBuilder) {
+    app.insert_resource(KillsByWeapon::default())
+        .add_system(record_kills.system().label("record_kills").after("fight"))
+        .add_system(
+            record_result
+                .system()
+                .after("creature_type_count")
+                .after("record_kills"),
+        );
+}
+
+// Prints the result of a headless game for other programs to read. Runs after the update stage
+// so the result inserted when the game ended is there.
+pub fn print_game_result(format: Res<OutputFormat>, result: Option<Res<GameResult>>) {
+    if let Some(result) = result {
+        if result.is_added() {
+            print_structured(*format, &*result);
+        }
+    }
+}
+
+// Plays a single headless game to completion, or returns None if it hits the tick limit
+fn play_game(seed: u64, data: &GameData) -> Result<Option<GameResult>, String> {
+    let mut app_builder = build_app(GameSetup::New { seed }, data.clone(), true)?;
+    add_result_systems(&mut app_builder);
+
+    let mut app = std::mem::take(&mut app_builder.app);
+
+    loop {
+        app.update();
+
+        if let Some(result) = app.world.remove_resource::<GameResult>() {
+            return Ok(Some(result));
+        }
+
+        if app.world.get_resource::<TickCount>().unwrap().0 >= MAX_TICKS {
+            return Ok(None);
+        }
+    }
+}
+
+// Aggregate statistics for a whole tournament
+#[derive(Serialize)]
+struct TournamentStats {
+    scenario: String,
+    seed: u64,
+    games: u32,
+    wins: BTreeMap<CreatureType, u32>,
+    draws: u32,
+    // Games abandoned after MAX_TICKS
+    unfinished: u32,
+    average_ticks: f64,
+    average_survivors: f64,
+    kills_by_weapon: BTreeMap<String, u32>,
+    results: Vec<GameResult>,
+}
+
+fn print_stats(stats: &TournamentStats) {
+    let percent = |count: u32| count as f64 * 100.0 / stats.games.max(1) as f64;
+
+    println!("Scenario: {}", stats.scenario);
+    println!("Tournament seed: {}", stats.seed);
+    println!("Games played: {}", stats.games);
+    println!();
+
+    let mut wins: Vec<(&CreatureType, &u32)> = stats.wins.iter().collect();
+    wins.sort_by(|a, b| b.1.cmp(a.1));
+    for (creature_type, count) in wins {
+        println!(
+            "{} wins: {} ({:.1}%)",
+            creature_type,
+            count,
+            percent(*count)
+        );
+    }
+    println!("Draws: {} ({:.1}%)", stats.draws, percent(stats.draws));
+    println!(
+        "Unfinished after {} ticks: {} ({:.1}%)",
+        MAX_TICKS,
+        stats.unfinished,
+        percent(stats.unfinished)
+    );
+    println!();
+
+    println!("Average game length: {:.1} ticks", stats.average_ticks);
+    println!("Average survivors: {:.2}", stats.average_survivors);
+    println!();
+
+    let mut kills_by_weapon: Vec<(&String, &u32)> = stats.kills_by_weapon.iter().collect();
+    kills_by_weapon.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    for (weapon, kills) in kills_by_weapon {
+        println!("Kills with {}: {}", weapon, kills);
+    }
+}
+
+/// Runs a number of headless battles back to back and prints aggregate statistics for balancing.
+pub fn run_tournament(games: u32, settings: GameSettings) -> Result<(), String> {
+    let data = GameData::load(&settings)?;
+
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    let mut rng = ChaCha12Rng::seed_from_u64(seed);
+
+    let mut wins: BTreeMap<CreatureType, u32> = BTreeMap::new();
+    let mut draws = 0;
+    let mut unfinished = 0;
+    let mut total_ticks = 0;
+    let mut total_survivors = 0;
+    let mut kills_by_weapon: BTreeMap<String, u32> = BTreeMap::new();
+    let mut results = Vec::new();
+
+    for _ in 0..games {
+        let result = match play_game(rng.gen(), &data)? {
+            Some(result) => result,
+            None => {
+                unfinished += 1;
+                continue;
+            }
+        };
+
+        match &result.winner {
+            Some(winner) => *wins.entry(winner.clone()).or_insert(0) += 1,
+            None => draws += 1,
+        }
+
+        total_ticks += result.ticks as u64;
+        total_survivors += result.survivors as u64;
+
+        for (weapon, kills) in result.kills_by_weapon.iter() {
+            *kills_by_weapon.entry(weapon.clone()).or_insert(0) += kills;
+        }
+
+        results.push(result);
+    }
+
+    let finished = (games - unfinished).max(1) as f64;
+
+    let stats = TournamentStats {
+        scenario: data.scenario.name.clone(),
+        seed,
+        games,
+        wins,
+        draws,
+        unfinished,
+        average_ticks: total_ticks as f64 / finished,
+        average_survivors: total_survivors as f64 / finished,
+        kills_by_weapon,
+        results,
+    };
+
+    match settings.format {
+        OutputFormat::Text => print_stats(&stats),
+        format => print_structured(format, &stats),
+    }
+
+    Ok(())
+}